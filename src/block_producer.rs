@@ -0,0 +1,211 @@
+use crate::seed;
+use crate::test_context::TestContext;
+use crate::tests::TestOptions;
+
+use rand::prelude::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A change to the network, applied as part of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnEvent {
+    /// Register a new snode (joins a random swarm)
+    AddSnode,
+    /// Create a new swarm with this many snodes
+    AddSwarm(usize),
+    /// Deregister a random snode
+    DropSnode,
+    /// Shut down a random snode without deregistering it
+    DisconnectSnode,
+    /// Shut down a random snode and bring it back after this many ms
+    RestartSnode(u64),
+    /// Dissolve a random swarm, moving its snodes to other swarms
+    DissolveSwarm,
+    /// Instead of a regular block, replace the last `depth` blocks
+    /// with `alt_len` alternative ones
+    Reorg { depth: u64, alt_len: u64 },
+}
+
+/// Decides on the events for every block that
+/// doesn't have any explicitly scheduled
+pub type EventPlan = Box<dyn FnMut(&TestContext) -> Vec<SnEvent> + Send>;
+
+/// A plan with no events, i.e. static swarms
+pub fn no_events() -> EventPlan {
+    Box::new(|_| vec![])
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BlockInterval {
+    Fixed(Duration),
+    /// Uniformly distributed in `base - jitter ..= base + jitter`
+    Jittered { base: Duration, jitter: Duration },
+}
+
+impl BlockInterval {
+    pub fn from_options(opt: &TestOptions) -> BlockInterval {
+        if opt.block_jitter == Duration::from_millis(0) {
+            BlockInterval::Fixed(opt.block_interval)
+        } else {
+            BlockInterval::Jittered {
+                base: opt.block_interval,
+                jitter: opt.block_jitter,
+            }
+        }
+    }
+
+    fn next(&self, rng: &mut StdRng) -> Duration {
+        match *self {
+            BlockInterval::Fixed(interval) => interval,
+            BlockInterval::Jittered { base, jitter } => {
+                let min = base.checked_sub(jitter).unwrap_or_default();
+                let max = base + jitter;
+                let ms = rng.gen_range(min.as_millis() as u64, max.as_millis() as u64 + 1);
+                Duration::from_millis(ms)
+            }
+        }
+    }
+}
+
+struct ProducerState {
+    paused: bool,
+    stopped: bool,
+    /// Blocks requested with `step`, produced right away
+    steps: u64,
+    /// Events for the next blocks, one entry per block
+    scheduled: VecDeque<Vec<SnEvent>>,
+}
+
+/// Produces blocks on its own thread, applying the events for every
+/// block together with the height bump (see `TestContext::produce_block`)
+pub struct BlockProducer {
+    state: Arc<(Mutex<ProducerState>, Condvar)>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl BlockProducer {
+    pub fn start(
+        ctx: &Arc<Mutex<TestContext>>,
+        interval: BlockInterval,
+        mut plan: EventPlan,
+    ) -> BlockProducer {
+        let state = ProducerState {
+            paused: false,
+            stopped: false,
+            steps: 0,
+            scheduled: VecDeque::new(),
+        };
+        let state = Arc::new((Mutex::new(state), Condvar::new()));
+
+        let mut rng = seed::derive_rng(ctx.lock().unwrap().seed(), seed::BLOCK_PRODUCER);
+
+        let ctx = Arc::clone(ctx);
+        let state_clone = Arc::clone(&state);
+
+        let thread = std::thread::spawn(move || {
+            let (lock, cvar) = &*state_clone;
+
+            let mut next_block = Instant::now() + interval.next(&mut rng);
+
+            for i in 0.. {
+                // Wait until it is time for the next block
+                let events = {
+                    let mut state = lock.lock().unwrap();
+
+                    loop {
+                        if state.stopped {
+                            return;
+                        }
+
+                        if state.steps > 0 {
+                            state.steps -= 1;
+                            break;
+                        }
+
+                        let now = Instant::now();
+
+                        if state.paused {
+                            state = cvar.wait(state).unwrap();
+                            // Start a fresh interval once resumed
+                            next_block = Instant::now() + interval.next(&mut rng);
+                        } else if now >= next_block {
+                            next_block = now + interval.next(&mut rng);
+                            break;
+                        } else {
+                            state = cvar.wait_timeout(state, next_block - now).unwrap().0;
+                        }
+                    }
+
+                    state.scheduled.pop_front()
+                };
+
+                println!("iteration: {}", i);
+                info!("iteration: {}", i);
+
+                // The scenario is over (it might have panicked)
+                let mut ctx = match ctx.lock() {
+                    Ok(ctx) if !ctx.is_stopped() => ctx,
+                    _ => return,
+                };
+
+                let events = events.unwrap_or_else(|| plan(&ctx));
+                ctx.produce_block(&events);
+
+                println!("swarms: {:?}", *ctx);
+            }
+        });
+
+        BlockProducer {
+            state,
+            thread: Some(thread),
+        }
+    }
+
+    fn update<F: FnOnce(&mut ProducerState)>(&self, f: F) {
+        let (lock, cvar) = &*self.state;
+        f(&mut lock.lock().unwrap());
+        cvar.notify_all();
+    }
+
+    pub fn pause(&self) {
+        self.update(|state| state.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.update(|state| state.paused = false);
+    }
+
+    /// Produce a block right away, whether paused or not
+    pub fn step(&self) {
+        self.update(|state| state.steps += 1);
+    }
+
+    /// Use `events` for the next block that doesn't have any scheduled
+    /// yet, instead of asking the plan
+    pub fn schedule(&self, events: Vec<SnEvent>) {
+        self.update(|state| state.scheduled.push_back(events));
+    }
+
+    /// Stop producing blocks, waiting for the current one to be finished.
+    /// Must not be called while holding the `TestContext` lock.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.update(|state| state.stopped = true);
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("block producer panicked");
+            }
+        }
+    }
+}
+
+impl Drop for BlockProducer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -1,19 +1,43 @@
 
 
+use crate::config::DaemonConfig;
+use crate::partition::{Partition, PartitionKind};
+use crate::seed;
+use crate::service_node::{NodeState, ServiceNode};
 use crate::swarms::*;
+use crate::timeline::Action;
+use crate::uptime::{LivenessAction, LivenessFailure, LivenessPolicy, Ping, PingLog};
 use rand::prelude::*;
+use sha2::{Digest, Sha256};
 
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{self, Debug, Display};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Instant, SystemTime};
 
-fn gen_random_hash() -> String {
-    let n1 = rand::thread_rng().gen::<u64>();
-    let n2 = rand::thread_rng().gen::<u64>();
-    let n3 = rand::thread_rng().gen::<u64>();
-    let n4 = rand::thread_rng().gen::<u64>();
+fn gen_random_hash(rng: &mut StdRng) -> String {
+    let n1 = rng.gen::<u64>();
+    let n2 = rng.gen::<u64>();
+    let n3 = rng.gen::<u64>();
+    let n4 = rng.gen::<u64>();
 
     format!("{:016x}{:016x}{:016x}{:016x}", n1, n2, n3, n4)
 }
 
+/// Hash of a block on top of `prev_hash`, committing
+/// to its height and the swarm layout at that height
+fn chain_hash(prev_hash: &str, height: u64, swarms: &[Swarm]) -> String {
+    let swarms = serde_json::to_vec(swarms).expect("could not serialize swarms");
+
+    let mut hasher = Sha256::new();
+    hasher.input(prev_hash.as_bytes());
+    hasher.input(height.to_le_bytes());
+    hasher.input(&swarms);
+
+    hex::encode(hasher.result())
+}
+
 #[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct KeyPair {
     pub pubkey: String,
@@ -26,17 +50,199 @@ pub struct X25519KeyPair {
     pub seckey: String,
 }
 
-#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Ed25519KeyPair {
     pub pubkey: String,
     pub seckey: String,
 }
 
+/// Reported for heights below the first scheduled hard fork
+const DEFAULT_HARDFORK: u8 = 15;
+
+/// Hard fork `version` takes effect at `height`
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HardFork {
+    pub height: u64,
+    pub version: u8,
+}
+
+/// The daemon reports itself `lag_blocks` behind the network (through
+/// `target_height`) for `duration_ms`, starting `start_ms` after genesis
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SyncPhase {
+    pub start_ms: u64,
+    pub duration_ms: u64,
+    pub lag_blocks: u64,
+}
+
+/// How many blocks behind the network a daemon with
+/// `phases` is, `elapsed` after genesis
+pub fn sync_lag(phases: &[SyncPhase], elapsed: std::time::Duration) -> u64 {
+    let ms = elapsed.as_millis() as u64;
+
+    phases
+        .iter()
+        .filter(|phase| phase.start_ms <= ms && ms < phase.start_ms + phase.duration_ms)
+        .map(|phase| phase.lag_blocks)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Settings for a new chain that come from the harness config
+#[derive(Debug, Clone, Default)]
+pub struct ChainParams {
+    pub hardforks: Vec<HardFork>,
+    pub sync_phases: Vec<SyncPhase>,
+    pub partitions: Vec<Partition>,
+    /// No policy means that pings are only recorded
+    pub liveness: Option<LivenessPolicy>,
+    /// Only recorded, along with their delays and faults,
+    /// since the daemons are run by the harness
+    pub daemons: Vec<DaemonConfig>,
+}
+
+/// A change in the swarm layout, found by comparing a block with its parent
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum BlockEvent {
+    SwarmCreated { swarm_id: u64 },
+    SwarmDissolved { swarm_id: u64 },
+    NodeRegistered { port: String, swarm_id: u64 },
+    NodeDeregistered { port: String, swarm_id: u64 },
+    NodeMoved { port: String, from: u64, to: u64 },
+    NodeStateChanged { port: String, state: NodeState },
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct BlockRecord {
+    pub height: u64,
+    pub hash: String,
+    pub hardfork: u8,
+    /// Swarm layout as of this block
+    pub swarms: Vec<Swarm>,
+    /// Nodes awaiting contributions as of this block
+    pub awaiting: Vec<ServiceNode>,
+    pub events: Vec<BlockEvent>,
+}
+
+impl BlockRecord {
+    /// The swarm responsible for `pk` as of this block
+    pub fn swarm_for_pk(&self, pk: &PubKey) -> Option<&Swarm> {
+        if self.swarms.is_empty() {
+            None
+        } else {
+            Some(&self.swarms[swarm_idx_for_pk(&self.swarms, pk)])
+        }
+    }
+}
+
+/// Sent to every subscriber (i.e. daemon view) whenever the tip of
+/// the chain changes, so that they never need to lock the chain
+#[derive(Debug, Clone)]
+pub struct ChainUpdate {
+    pub block: BlockRecord,
+    /// When the tip changed, to add propagation delays to
+    pub time: Instant,
+    pub genesis: SystemTime,
+    /// Periods during which all daemons are syncing
+    pub sync_phases: Vec<SyncPhase>,
+    /// Each daemon only applies its own
+    pub partitions: Vec<Partition>,
+}
+
+fn block_events(prev: &[Swarm], cur: &[Swarm]) -> Vec<BlockEvent> {
+    // pubkey -> (port, swarm id, state)
+    let nodes = |swarms: &[Swarm]| -> BTreeMap<String, (String, u64, NodeState)> {
+        swarms
+            .iter()
+            .flat_map(|swarm| {
+                swarm.nodes.iter().map(move |sn| {
+                    (sn.pubkey.clone(), (sn.port.clone(), swarm.swarm_id, sn.state))
+                })
+            })
+            .collect()
+    };
+
+    let prev_ids: BTreeSet<u64> = prev.iter().map(|swarm| swarm.swarm_id).collect();
+    let cur_ids: BTreeSet<u64> = cur.iter().map(|swarm| swarm.swarm_id).collect();
+
+    let prev_nodes = nodes(prev);
+    let cur_nodes = nodes(cur);
+
+    let mut events = vec![];
+
+    for &swarm_id in cur_ids.difference(&prev_ids) {
+        events.push(BlockEvent::SwarmCreated { swarm_id });
+    }
+
+    for (pk, (port, swarm_id, state)) in &cur_nodes {
+        match prev_nodes.get(pk) {
+            None => events.push(BlockEvent::NodeRegistered {
+                port: port.clone(),
+                swarm_id: *swarm_id,
+            }),
+            Some((_, from, prev_state)) => {
+                if from != swarm_id {
+                    events.push(BlockEvent::NodeMoved {
+                        port: port.clone(),
+                        from: *from,
+                        to: *swarm_id,
+                    });
+                }
+
+                // Credit running down doesn't count as a change
+                if std::mem::discriminant(prev_state) != std::mem::discriminant(state) {
+                    events.push(BlockEvent::NodeStateChanged {
+                        port: port.clone(),
+                        state: *state,
+                    });
+                }
+            }
+        }
+    }
+
+    for (pk, (port, swarm_id, _)) in &prev_nodes {
+        if !cur_nodes.contains_key(pk) {
+            events.push(BlockEvent::NodeDeregistered {
+                port: port.clone(),
+                swarm_id: *swarm_id,
+            });
+        }
+    }
+
+    for &swarm_id in prev_ids.difference(&cur_ids) {
+        events.push(BlockEvent::SwarmDissolved { swarm_id });
+    }
+
+    events
+}
+
+fn hardfork_at(hardforks: &[HardFork], height: u64) -> u8 {
+    hardforks
+        .iter()
+        .rev()
+        .find(|hf| hf.height <= height)
+        .map_or(DEFAULT_HARDFORK, |hf| hf.version)
+}
+
 pub struct Blockchain {
     pub swarm_manager: SwarmManager,
-    height: u64,
-    block_hash: String,
-    sys_time: std::time::SystemTime,
+    /// Every block since genesis, the last one being the current block
+    history: Vec<BlockRecord>,
+    /// Sorted by height
+    hardforks: Vec<HardFork>,
+    sync_phases: Vec<SyncPhase>,
+    partitions: Vec<Partition>,
+    sys_time: SystemTime,
+    subscribers: Vec<Sender<ChainUpdate>>,
+    pings: PingLog,
+    liveness: Option<LivenessPolicy>,
+    /// When each node was first seen in a swarm, by port
+    first_seen: HashMap<String, u64>,
+    /// Nodes decommissioned by the liveness policy, and when
+    penalised: HashMap<String, u64>,
+    liveness_failures: Vec<LivenessFailure>,
 }
 
 impl Display for Blockchain {
@@ -64,45 +270,362 @@ impl Debug for Blockchain {
 }
 
 impl Blockchain {
-    pub fn new(bin_path: &str) -> Blockchain {
+    pub fn new(bin_path: &str, playground: &Path, seed: u64, params: &ChainParams) -> Blockchain {
         // we use 20, so blockchain testing starts immediately
         // (also, 0 is used to indicate that SN haven't synced yet)
         let height = 20;
-        let block_hash = gen_random_hash();
+        // The genesis hash is the only random one, every
+        // following block is chained on top of it
+        let mut rng = seed::derive_rng(seed, seed::BLOCKCHAIN);
+        let block_hash = gen_random_hash(&mut rng);
+
+        let mut swarm_manager = SwarmManager::new(bin_path, playground, seed);
+        swarm_manager.event_log.set_height(height);
 
-        let swarm_manager = SwarmManager::new(bin_path);
+        let mut hardforks = params.hardforks.clone();
+        hardforks.sort_by_key(|hf| hf.height);
+
+        swarm_manager.recorder.record(Action::Genesis {
+            seed,
+            hardforks: hardforks.clone(),
+            sync_phases: params.sync_phases.clone(),
+            partitions: params.partitions.clone(),
+            daemons: params.daemons.clone(),
+        });
+
+        let genesis = BlockRecord {
+            height,
+            hash: block_hash,
+            hardfork: hardfork_at(&hardforks, height),
+            swarms: swarm_manager.get_swarms(),
+            awaiting: swarm_manager.awaiting.clone(),
+            events: vec![],
+        };
 
         Blockchain {
             swarm_manager,
-            height,
-            block_hash,
-            sys_time: std::time::SystemTime::now(),
+            history: vec![genesis],
+            hardforks,
+            sync_phases: params.sync_phases.clone(),
+            partitions: params.partitions.clone(),
+            sys_time: SystemTime::now(),
+            subscribers: vec![],
+            pings: PingLog::default(),
+            liveness: params.liveness,
+            first_seen: HashMap::new(),
+            penalised: HashMap::new(),
+            liveness_failures: vec![],
         }
     }
 
-    pub fn get_target_height(&self) -> u64 {
+    fn update(&self) -> ChainUpdate {
+        ChainUpdate {
+            block: self.current_block().clone(),
+            time: Instant::now(),
+            genesis: self.sys_time,
+            sync_phases: self.sync_phases.clone(),
+            partitions: self.partitions.clone(),
+        }
+    }
 
-        if self.sys_time.elapsed().unwrap() >= std::time::Duration::from_secs(0) {
-            self.height
-        } else {
-            20
+    fn notify(&mut self) {
+        let update = self.update();
+
+        // Subscribers that are gone don't need to be told anymore
+        self.subscribers
+            .retain(|tx| tx.send(update.clone()).is_ok());
+    }
+
+    /// Get a `ChainUpdate` for the current tip right away, and then one
+    /// for every change
+    pub fn subscribe(&mut self) -> Receiver<ChainUpdate> {
+        let (tx, rx) = mpsc::channel();
+        self.add_subscriber(tx);
+        rx
+    }
+
+    fn add_subscriber(&mut self, tx: Sender<ChainUpdate>) {
+        if tx.send(self.update()).is_ok() {
+            self.subscribers.push(tx);
         }
     }
 
-    pub fn get_height(&self) -> u64 {
-        self.height
+    /// The block at the tip of the chain
+    pub fn current_block(&self) -> &BlockRecord {
+        self.history.last().expect("no genesis block")
+    }
+
+    /// Time since genesis
+    pub fn elapsed(&self) -> std::time::Duration {
+        self.sys_time.elapsed().unwrap_or_default()
+    }
+
+    /// Higher than the current height while the daemon is syncing
+    pub fn get_target_height(&self) -> u64 {
+        self.get_height() + sync_lag(&self.sync_phases, self.elapsed())
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.get_target_height() > self.get_height()
+    }
+
+    /// Start syncing now, for `duration`
+    pub fn start_syncing(&mut self, lag_blocks: u64, duration: std::time::Duration) {
+        let phase = SyncPhase {
+            start_ms: self.elapsed().as_millis() as u64,
+            duration_ms: duration.as_millis() as u64,
+            lag_blocks,
+        };
+
+        info!("syncing: {:?}", phase);
+
+        self.swarm_manager.recorder.record(Action::SyncStarted {
+            lag_blocks,
+            duration_ms: phase.duration_ms,
+        });
+
+        self.sync_phases.push(phase);
+        self.notify();
     }
 
-    pub fn get_block_hash(&self) -> &String {
-        &self.block_hash
+    /// Cut the daemon on `daemon` off from (part of) the network now, for `duration`
+    pub fn start_partition(&mut self, daemon: u16, kind: PartitionKind, duration: std::time::Duration) {
+        let partition = Partition {
+            daemon,
+            start_ms: self.elapsed().as_millis() as u64,
+            duration_ms: duration.as_millis() as u64,
+            kind: kind.clone(),
+        };
+
+        info!("partition: {:?}", partition);
+
+        self.swarm_manager.recorder.record(Action::PartitionStarted {
+            daemon,
+            duration_ms: partition.duration_ms,
+            kind,
+        });
+
+        self.partitions.push(partition);
+        self.notify();
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.current_block().height
     }
 
+    /// Produce a new block with the current swarm layout
     pub fn inc_block_height(&mut self) {
-        self.height += 1;
-        self.block_hash = gen_random_hash();
+        let swarms = self.swarm_manager.get_swarms();
+        let awaiting = self.swarm_manager.awaiting.clone();
+
+        let prev = self.current_block();
+        let height = prev.height + 1;
+        let hash = chain_hash(&prev.hash, height, &swarms);
+        let events = block_events(&prev.swarms, &swarms);
+        let hardfork = hardfork_at(&self.hardforks, height);
+
+        if hardfork != prev.hardfork {
+            println!("hard fork {} at height {}", hardfork, height);
+            info!("hard fork {} at height {}", hardfork, height);
+        }
+
+        for event in &events {
+            info!("block {}: {:?}", height, event);
+        }
+
+        self.swarm_manager.recorder.record(Action::Block {
+            height,
+            swarms: swarms.clone(),
+            awaiting: awaiting.clone(),
+        });
+
+        self.history.push(BlockRecord {
+            height,
+            hash,
+            hardfork,
+            swarms,
+            awaiting,
+            events,
+        });
+
+        self.swarm_manager.event_log.set_height(height);
+        self.notify();
+    }
+
+    /// Add a hard fork to the schedule (replacing any other one at the
+    /// same height). Only future heights can be scheduled.
+    pub fn schedule_hardfork(&mut self, fork: HardFork) -> Result<(), ()> {
+        if fork.height <= self.get_height() {
+            return Err(());
+        }
+
+        self.hardforks.retain(|hf| hf.height != fork.height);
+        self.hardforks.push(fork);
+        self.hardforks.sort_by_key(|hf| hf.height);
+
+        info!("scheduled hard fork {} at height {}", fork.version, fork.height);
+
+        self.swarm_manager.recorder.record(Action::HardforkScheduled {
+            height: fork.height,
+            version: fork.version,
+        });
+
+        Ok(())
+    }
+
+    /// Roll back the last `depth` blocks, as if a competing chain took over.
+    /// The swarm manager is left as it is, so the blocks that follow
+    /// reflect the current swarm layout rather than the abandoned one.
+    pub fn rollback(&mut self, depth: u64) -> Result<(), ()> {
+        // Can't go past genesis
+        if depth == 0 || depth as usize >= self.history.len() {
+            return Err(());
+        }
+
+        let fork = self.history.len() - depth as usize;
+        let orphaned = self.history.split_off(fork);
+
+        for block in &orphaned {
+            info!("reorg: orphaned block {} {}", block.height, block.hash);
+        }
+
+        let height = self.get_height();
+        self.swarm_manager.event_log.set_height(height);
+        self.swarm_manager.recorder.record(Action::Rollback { depth });
+        self.notify();
+
+        Ok(())
+    }
+
+    pub fn history(&self) -> &[BlockRecord] {
+        &self.history
+    }
+
+    pub fn block_at(&self, height: u64) -> Option<&BlockRecord> {
+        let genesis = self.history[0].height;
+
+        height
+            .checked_sub(genesis)
+            .and_then(|idx| self.history.get(idx as usize))
+    }
+
+    /// The swarm that was responsible for `pk` at `height`
+    pub fn swarm_for_pk_at(&self, pk: &PubKey, height: u64) -> Option<&Swarm> {
+        self.block_at(height)
+            .and_then(|block| block.swarm_for_pk(pk))
     }
 
     pub fn get_swarms(&self) -> Vec<Swarm> {
         self.swarm_manager.get_swarms()
     }
+
+    /// Record an uptime proof, matching it with a registered
+    /// node by its storage port or by one of its pubkeys
+    pub fn record_ping(&mut self, mut ping: Ping) {
+        ping.time_ms = self.elapsed().as_millis() as u64;
+
+        let sm = &self.swarm_manager;
+        let port = sm
+            .swarms
+            .iter()
+            .flat_map(|swarm| swarm.nodes.iter())
+            .chain(sm.awaiting.iter())
+            .find(|sn| match (&ping.port, &ping.pubkey) {
+                (Some(port), _) => &sn.port == port,
+                (None, Some(pk)) => {
+                    &sn.ed_keys.pubkey == pk || &sn.pubkey == pk || &sn.pubkey_x25519 == pk
+                }
+                (None, None) => false,
+            })
+            .map(|sn| sn.port.clone());
+
+        match port {
+            Some(port) => {
+                trace!("ping from snode {}: {:?}", port, ping);
+                self.pings.record(&port, ping);
+            }
+            None => {
+                warn!("ping from an unknown snode: {:?}", ping);
+                self.pings.unknown += 1;
+            }
+        }
+    }
+
+    pub fn pings(&self) -> &PingLog {
+        &self.pings
+    }
+
+    /// Nodes penalised by the liveness policy so far
+    pub fn liveness_failures(&self) -> &[LivenessFailure] {
+        &self.liveness_failures
+    }
+
+    /// Apply the liveness policy, like oxend does with every block: active
+    /// nodes that haven't pinged within the window are decommissioned or
+    /// deregistered, and the ones it decommissioned are recommissioned
+    /// once they ping again
+    pub fn enforce_liveness(&mut self) {
+        let policy = match self.liveness {
+            Some(policy) => policy,
+            None => return,
+        };
+
+        let now = self.elapsed().as_millis() as u64;
+        let height = self.get_height();
+
+        let nodes: Vec<ServiceNode> = self
+            .swarm_manager
+            .swarms
+            .iter()
+            .flat_map(|swarm| swarm.nodes.iter().cloned())
+            .collect();
+
+        // Nodes deregistered (or recommissioned by hand) in the meantime
+        self.penalised.retain(|port, _| {
+            nodes
+                .iter()
+                .any(|sn| &sn.port == port && !sn.is_active())
+        });
+
+        for sn in &nodes {
+            let first_seen = *self.first_seen.entry(sn.port.clone()).or_insert(now);
+            let last_ping = self.pings.last_ms(&sn.port);
+
+            if let Some(&since) = self.penalised.get(&sn.port) {
+                if last_ping.is_some_and(|ms| ms > since) {
+                    info!("snode {} pinged again", sn.port);
+                    self.penalised.remove(&sn.port);
+                    let _ = self.swarm_manager.recommission_snode(&sn.port);
+                }
+                continue;
+            }
+
+            let silent_ms = now.saturating_sub(last_ping.map_or(first_seen, |ms| ms.max(first_seen)));
+
+            if !sn.is_active() || silent_ms <= policy.window_ms {
+                continue;
+            }
+
+            warn!("snode {} hasn't pinged for {} ms", sn.port, silent_ms);
+
+            let penalised = match policy.action {
+                LivenessAction::Decommission => self
+                    .swarm_manager
+                    .decommission_snode(&sn.port, policy.credit_blocks)
+                    .map(|()| {
+                        self.penalised.insert(sn.port.clone(), now);
+                    }),
+                LivenessAction::Deregister => self.swarm_manager.deregister_snode(&sn.port),
+            };
+
+            if penalised.is_ok() {
+                self.liveness_failures.push(LivenessFailure {
+                    port: sn.port.clone(),
+                    height,
+                    silent_ms,
+                    action: policy.action,
+                });
+            }
+        }
+    }
 }
@@ -334,12 +334,22 @@ pub fn send_random_message_to_pk(
     res.map(|()| msg)
 }
 
+/// The node that messages for `pk` are sent to
+pub fn target_node<'a>(sm: &'a SwarmManager, pk: &PubKey) -> &'a ServiceNode {
+    let swarm_idx = sm.get_swarm_by_pk(pk);
+
+    // Note: not a random node, as we want to make sure we send messages to a snode
+    // that is online (for testing disconnected snodes in some tests)
+    let nodes = &sm.swarms[swarm_idx as usize].nodes;
+    nodes.iter().find(|sn| sn.is_active()).unwrap_or(&nodes[0])
+}
+
 pub fn send_message_to_pk(sm: &SwarmManager, pk_str: &str, msg: &str) -> Result<(), ()> {
     let pk = PubKey::new(&pk_str).unwrap();
 
     let swarm_idx = sm.get_swarm_by_pk(&pk);
 
-    let sn = &sm.swarms[swarm_idx as usize].nodes[0];
+    let sn = target_node(sm, &pk);
 
     let res = send_message(&sn.port, &pk_str, &msg);
 
@@ -399,10 +409,7 @@ pub fn send_random_message(
 
     let swarm_idx = sm.get_swarm_by_pk(&pk);
 
-    // Note: commented out, as we want to make sure we send messages to a snode
-    // that is online (for testing disconnected snodes in some tests)
-    // let sn = &sm.swarms[swarm_idx as usize].nodes.choose(&mut rng).unwrap();
-    let sn = &sm.swarms[swarm_idx as usize].nodes[0];
+    let sn = target_node(sm, &pk);
 
     let num = rng.gen::<u64>();
     let msg = num.to_string() + &num.to_string() + &num.to_string();
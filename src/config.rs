@@ -0,0 +1,266 @@
+use crate::blockchain::{ChainParams, HardFork, SyncPhase};
+use crate::faults::{Fault, FaultPhase};
+use crate::partition::{Partition, PartitionKind};
+use crate::tests::{self, TestOptions};
+use serde::de::{Deserialize, Deserializer, Error};
+use crate::uptime::LivenessPolicy;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::time::Duration;
+
+/// An oxend stand-in. Having several of them with different
+/// delays simulates block propagation.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DaemonConfig {
+    pub port: u16,
+    /// How long it takes for new blocks to reach this daemon
+    pub delay_ms: u64,
+    /// Periods during which only this daemon is syncing
+    #[serde(default)]
+    pub sync_phases: Vec<SyncPhase>,
+    /// Periods during which this daemon misbehaves
+    #[serde(default)]
+    pub faults: Vec<FaultPhase>,
+}
+
+impl DaemonConfig {
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
+}
+
+/// Values for `TestOptions`; anything left out falls back
+/// to whatever has been set at a lower level
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct OptionsConfig {
+    pub reliable_snodes: Option<bool>,
+    pub duration_secs: Option<u64>,
+    pub block_interval_ms: Option<u64>,
+    pub block_jitter_ms: Option<u64>,
+    pub message_interval_ms: Option<u64>,
+}
+
+impl OptionsConfig {
+    fn apply(&self, opt: &mut TestOptions) {
+        if let Some(reliable_snodes) = self.reliable_snodes {
+            opt.reliable_snodes = reliable_snodes;
+        }
+        if let Some(secs) = self.duration_secs {
+            opt.duration = Duration::from_secs(secs);
+        }
+        if let Some(ms) = self.block_interval_ms {
+            opt.block_interval = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.block_jitter_ms {
+            opt.block_jitter = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.message_interval_ms {
+            opt.message_interval = Duration::from_millis(ms);
+        }
+    }
+}
+
+/// Per-scenario overrides: a profile to start from
+/// and/or individual values
+#[derive(Debug, Clone, Default)]
+pub struct ScenarioConfig {
+    pub profile: Option<String>,
+    pub options: OptionsConfig,
+}
+
+// `#[serde(flatten)]` would ignore unknown fields, so
+// leave the rest of the section to `OptionsConfig`
+impl<'de> Deserialize<'de> for ScenarioConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ScenarioConfig, D::Error> {
+        let mut section = toml::value::Table::deserialize(deserializer)?;
+
+        let profile = match section.remove("profile") {
+            Some(toml::Value::String(profile)) => Some(profile),
+            Some(other) => return Err(D::Error::custom(format!("invalid profile: {}", other))),
+            None => None,
+        };
+
+        let options = toml::Value::Table(section)
+            .try_into()
+            .map_err(D::Error::custom)?;
+
+        Ok(ScenarioConfig { profile, options })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HarnessConfig {
+    /// Seed for all random choices; a random one is used if not set
+    pub seed: Option<u64>,
+    /// Shut down as soon as `run` is finished instead of
+    /// opening the interactive console (same as `--headless`)
+    pub headless: bool,
+    /// Number of run directories to keep in `runs/`
+    /// (same as `--keep-runs`); 0 keeps all of them
+    pub keep_runs: usize,
+    /// Profile used when neither the command line
+    /// nor the scenario section names one
+    pub profile: Option<String>,
+    pub daemons: Vec<DaemonConfig>,
+    /// Hard fork schedule reported by the daemons
+    pub hardforks: Vec<HardFork>,
+    /// Periods during which all daemons are syncing
+    pub sync_phases: Vec<SyncPhase>,
+    /// Periods during which a daemon is cut off from the network
+    pub partitions: Vec<Partition>,
+    /// What happens to nodes that stop sending uptime proofs
+    pub liveness: Option<LivenessPolicy>,
+    pub options: OptionsConfig,
+    pub profiles: HashMap<String, OptionsConfig>,
+    pub scenarios: HashMap<String, ScenarioConfig>,
+}
+
+impl Default for HarnessConfig {
+    fn default() -> HarnessConfig {
+        let daemons = [(22129, 100), (22139, 300), (22149, 500)]
+            .iter()
+            .map(|&(port, delay_ms)| DaemonConfig {
+                port,
+                delay_ms,
+                sync_phases: vec![],
+                faults: vec![],
+            })
+            .collect();
+
+        HarnessConfig {
+            seed: None,
+            headless: false,
+            keep_runs: 20,
+            profile: None,
+            daemons,
+            hardforks: vec![],
+            sync_phases: vec![],
+            partitions: vec![],
+            liveness: None,
+            options: OptionsConfig::default(),
+            profiles: HashMap::new(),
+            scenarios: HashMap::new(),
+        }
+    }
+}
+
+impl HarnessConfig {
+    pub fn load(path: &str) -> Result<HarnessConfig, String> {
+        let mut contents = String::new();
+
+        std::fs::File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("could not read {}: {}", path, e))?;
+
+        let config: HarnessConfig =
+            toml::from_str(&contents).map_err(|e| format!("could not parse {}: {}", path, e))?;
+
+        if config.daemons.is_empty() {
+            return Err(format!("{}: at least one daemon is required", path));
+        }
+
+        let mut ports = config.lokid_ports();
+        ports.sort_unstable();
+        if let Some(pair) = ports.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(format!("{}: two daemons on port {}", path, pair[0]));
+        }
+
+        if let Some(name) = config.scenarios.keys().find(|name| tests::find_scenario(name).is_none()) {
+            return Err(format!("{}: unknown scenario: {}", path, name));
+        }
+
+        let sections = std::iter::once(("options", &config.options))
+            .chain(config.profiles.iter().map(|(name, options)| (name.as_str(), options)))
+            .chain(config.scenarios.iter().map(|(name, scenario)| (name.as_str(), &scenario.options)));
+
+        for (name, options) in sections {
+            if options.block_interval_ms == Some(0) {
+                return Err(format!("{}: block_interval_ms can't be 0 ({})", path, name));
+            }
+        }
+
+        for daemon in &config.daemons {
+            for phase in &daemon.faults {
+                if let Fault::Flap { up_ms, down_ms } = phase.fault {
+                    if up_ms == 0 || down_ms == 0 {
+                        return Err(format!(
+                            "{}: daemon {} can't flap with a zero up_ms or down_ms",
+                            path, daemon.port
+                        ));
+                    }
+                }
+            }
+        }
+
+        let ports = config.lokid_ports();
+
+        for partition in &config.partitions {
+            let mut daemons = vec![partition.daemon];
+            if let PartitionKind::Subset { daemons: reachable } = &partition.kind {
+                daemons.extend(reachable);
+            }
+
+            if let Some(port) = daemons.iter().find(|port| !ports.contains(port)) {
+                return Err(format!("{}: partition of an unknown daemon: {}", path, port));
+            }
+        }
+
+        let mut hardforks = config.hardforks.clone();
+        hardforks.sort_by_key(|hf| hf.height);
+
+        for pair in hardforks.windows(2) {
+            if pair[0].height == pair[1].height {
+                return Err(format!("{}: two hard forks at height {}", path, pair[0].height));
+            }
+            if pair[0].version > pair[1].version {
+                return Err(format!("{}: hard fork versions must not decrease", path));
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn chain_params(&self) -> ChainParams {
+        ChainParams {
+            hardforks: self.hardforks.clone(),
+            sync_phases: self.sync_phases.clone(),
+            partitions: self.partitions.clone(),
+            liveness: self.liveness,
+            daemons: self.daemons.clone(),
+        }
+    }
+
+    pub fn lokid_ports(&self) -> Vec<u16> {
+        self.daemons.iter().map(|d| d.port).collect()
+    }
+
+    /// Resolve options for `scenario`. Later levels take precedence:
+    /// defaults, `[options]`, the profile (`profile` argument, then the
+    /// scenario's own, then the top-level one) and the scenario section.
+    pub fn test_options(&self, scenario: &str, profile: Option<&str>) -> Result<TestOptions, String> {
+        let mut opt = TestOptions::default();
+
+        self.options.apply(&mut opt);
+
+        let scenario_cfg = self.scenarios.get(scenario);
+
+        let profile = profile
+            .or_else(|| scenario_cfg.and_then(|s| s.profile.as_deref()))
+            .or(self.profile.as_deref());
+
+        if let Some(name) = profile {
+            match self.profiles.get(name) {
+                Some(profile) => profile.apply(&mut opt),
+                None => return Err(format!("unknown profile: {}", name)),
+            }
+        }
+
+        if let Some(scenario_cfg) = scenario_cfg {
+            scenario_cfg.options.apply(&mut opt);
+        }
+
+        Ok(opt)
+    }
+}
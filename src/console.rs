@@ -0,0 +1,393 @@
+use crate::block_producer::{self, BlockInterval, BlockProducer, SnEvent};
+use crate::client;
+use crate::partition::PartitionKind;
+use crate::swarms::{PubKey, Swarm};
+use crate::test_context::TestContext;
+
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex};
+
+const HELP: &str = "\
+Swarm changes only become visible to the storage servers with the next `block`.
+
+  add-node              register a new snode (joins a random swarm)
+  add-swarm <n>         create a new swarm with <n> snodes
+  drop                  deregister a random snode
+  disconnect            shut down a random snode without deregistering it
+  restart <port> <ms>   shut down the snode on <port> and bring it back after <ms>
+  dissolve <swarm id>   dissolve a swarm, moving its snodes to other swarms
+  add-unfunded          register a new snode that awaits contributions (not in any swarm)
+  fund <port>           fund the snode on <port>, so that it joins a swarm
+  decommission <port> [n]
+                        decommission the snode on <port>, deregistering it after <n> blocks
+                        (default: 100) unless recommissioned
+  recommission <port>   recommission the snode on <port>
+  block                 produce a new block
+  reorg <depth> <n>     replace the last <depth> blocks with <n> alternative ones
+  hardfork <height> <v> schedule hard fork version <v> at a future <height>
+  sync <lag> <ms>       make the daemons report being <lag> blocks behind for <ms>
+  partition <daemon> <ms> [<daemon>...]
+                        for <ms>, the daemon on port <daemon> only knows about snodes
+                        attached to itself or to the other listed daemons
+  partition <daemon> <ms> fork
+                        for <ms>, the daemon on port <daemon> follows a fork of its own
+                        without any membership changes
+  swarms                print the current swarms
+  history               print every block along with its swarm changes
+  events [n]            print the last <n> service node events (default: all)
+  pings                 print the latest uptime proof from each snode
+  owner <pk> [height]   print the swarm responsible for <pk> at <height> (default: current)
+  send                  send a random message to a random pubkey
+  send <pk> <msg>       send <msg> to <pk>
+  check                 check that all sent messages are still available
+  test                  print all messages stored on every snode
+
+Blocks can also be produced automatically:
+
+  produce <ms>          produce a block every <ms>
+  produce stop          stop producing blocks
+  pause                 pause block production
+  resume                resume block production
+  step                  produce a block right away (also while paused)
+  schedule <event>      apply <event> with the next block, where <event> is one of
+                        add-node, add-swarm <n>, drop, disconnect, restart <ms>, dissolve
+
+  help                  print this message
+  quit                  shut down all snodes and exit";
+
+fn print_sn_data(swarm: &Swarm) {
+    for sn in &swarm.nodes {
+        println!("[{}]", &sn.port);
+
+        let messages = client::request_all_messages(&sn.port);
+
+        for msg in messages {
+            println!("  {} {}", msg.pk, msg.data);
+        }
+    }
+}
+
+/// Blocks a node can stay decommissioned for, unless specified
+const DEFAULT_CREDIT_BLOCKS: u64 = 100;
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<&str>, what: &str) -> Result<T, String> {
+    let arg = arg.ok_or_else(|| format!("missing {}", what))?;
+
+    arg.parse::<T>()
+        .map_err(|_| format!("invalid {}: {}", what, arg))
+}
+
+fn parse_pubkey(arg: Option<&str>) -> Result<&str, String> {
+    let pk = arg.ok_or("missing pubkey")?;
+
+    if pk.len() != 64 || !pk.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid pubkey: {}", pk));
+    }
+
+    Ok(pk)
+}
+
+fn parse_event<'a>(mut args: impl Iterator<Item = &'a str>) -> Result<SnEvent, String> {
+    let event = match args.next().ok_or("missing event")? {
+        "add-node" => SnEvent::AddSnode,
+        "add-swarm" => SnEvent::AddSwarm(parse_arg(args.next(), "number of snodes")?),
+        "drop" => SnEvent::DropSnode,
+        "disconnect" => SnEvent::DisconnectSnode,
+        "restart" => SnEvent::RestartSnode(parse_arg(args.next(), "delay")?),
+        "dissolve" => SnEvent::DissolveSwarm,
+        event => return Err(format!("unknown event: {}", event)),
+    };
+
+    Ok(event)
+}
+
+fn require_producer(producer: &Option<BlockProducer>) -> Result<&BlockProducer, String> {
+    producer
+        .as_ref()
+        .ok_or_else(|| "blocks are not being produced (try `produce`)".to_owned())
+}
+
+/// Most commands pick a random swarm or snode
+fn require_swarms(ctx: &Arc<Mutex<TestContext>>) -> Result<(), String> {
+    if ctx.lock().unwrap().get_swarms().is_empty() {
+        Err("there are no swarms yet (try `add-swarm`)".to_owned())
+    } else {
+        Ok(())
+    }
+}
+
+/// Execute a single console command. Returns `Ok(false)` when asked to quit.
+fn execute(
+    ctx: &Arc<Mutex<TestContext>>,
+    producer: &mut Option<BlockProducer>,
+    line: &str,
+) -> Result<bool, String> {
+    let mut args = line.split_whitespace();
+
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Ok(true),
+    };
+
+    match command {
+        "quit" | "q" => return Ok(false),
+        "help" | "h" => println!("{}", HELP),
+        "add-node" => {
+            require_swarms(ctx)?;
+            ctx.lock().unwrap().add_snode();
+        }
+        "add-swarm" => {
+            let n = parse_arg::<usize>(args.next(), "number of snodes")?;
+            ctx.lock().unwrap().add_swarm(n);
+        }
+        "drop" => {
+            require_swarms(ctx)?;
+            ctx.lock().unwrap().drop_snode();
+        }
+        "disconnect" => {
+            require_swarms(ctx)?;
+            ctx.lock().unwrap().disconnect_snode();
+        }
+        "restart" => {
+            let port = args.next().ok_or("missing port")?;
+            let delay_ms = parse_arg::<u64>(args.next(), "delay")?;
+            ctx.lock()
+                .unwrap()
+                .restart_snode_by_port(port, delay_ms)
+                .map_err(|()| format!("no snode on port {}", port))?;
+        }
+        "add-unfunded" => match ctx.lock().unwrap().add_unfunded_snode() {
+            Some(port) => println!("snode {} awaits contributions", port),
+            None => return Err("no port available".to_owned()),
+        },
+        "fund" => {
+            let port = args.next().ok_or("missing port")?;
+            ctx.lock()
+                .unwrap()
+                .fund_snode(port)
+                .map_err(|()| format!("no snode awaiting contributions on port {}", port))?;
+        }
+        "decommission" => {
+            let port = args.next().ok_or("missing port")?;
+            let credit = match args.next() {
+                Some(credit) => parse_arg::<u64>(Some(credit), "number of blocks")?,
+                None => DEFAULT_CREDIT_BLOCKS,
+            };
+            ctx.lock()
+                .unwrap()
+                .decommission_snode(port, credit)
+                .map_err(|()| format!("no active snode on port {}", port))?;
+        }
+        "recommission" => {
+            let port = args.next().ok_or("missing port")?;
+            ctx.lock()
+                .unwrap()
+                .recommission_snode(port)
+                .map_err(|()| format!("no decommissioned snode on port {}", port))?;
+        }
+        "dissolve" => {
+            let swarm_id = parse_arg::<u64>(args.next(), "swarm id")?;
+            let mut ctx = ctx.lock().unwrap();
+            let idx = ctx
+                .get_swarms()
+                .iter()
+                .position(|s| s.swarm_id == swarm_id)
+                .ok_or_else(|| format!("no swarm with id {}", swarm_id))?;
+            ctx.dissolve_swarm(idx);
+        }
+        "block" => ctx.lock().unwrap().inc_block_height(),
+        "produce" => match args.next() {
+            Some("stop") => {
+                if let Some(producer) = producer.take() {
+                    producer.stop();
+                }
+            }
+            arg => {
+                if producer.is_some() {
+                    return Err("blocks are already being produced".to_owned());
+                }
+
+                let ms = parse_arg::<u64>(arg, "block interval")?;
+                let interval = BlockInterval::Fixed(std::time::Duration::from_millis(ms));
+                *producer = Some(BlockProducer::start(
+                    ctx,
+                    interval,
+                    block_producer::no_events(),
+                ));
+            }
+        },
+        "pause" => require_producer(producer)?.pause(),
+        "resume" => require_producer(producer)?.resume(),
+        "step" => require_producer(producer)?.step(),
+        "schedule" => {
+            let event = parse_event(args)?;
+            require_producer(producer)?.schedule(vec![event]);
+        }
+        "reorg" => {
+            let depth = parse_arg::<u64>(args.next(), "depth")?;
+            let alt_len = parse_arg::<u64>(args.next(), "number of blocks")?;
+            require_swarms(ctx)?;
+            ctx.lock()
+                .unwrap()
+                .reorg(depth, alt_len)
+                .map_err(|()| format!("can't replace the last {} blocks with {}", depth, alt_len))?;
+        }
+        "hardfork" => {
+            let height = parse_arg::<u64>(args.next(), "height")?;
+            let version = parse_arg::<u8>(args.next(), "version")?;
+            ctx.lock()
+                .unwrap()
+                .schedule_hardfork(height, version)
+                .map_err(|()| format!("height {} is not in the future", height))?;
+        }
+        "sync" => {
+            let lag = parse_arg::<u64>(args.next(), "lag")?;
+            let ms = parse_arg::<u64>(args.next(), "duration")?;
+            ctx.lock()
+                .unwrap()
+                .start_syncing(lag, std::time::Duration::from_millis(ms));
+        }
+        "partition" => {
+            let daemon = parse_arg::<u16>(args.next(), "daemon")?;
+            let ms = parse_arg::<u64>(args.next(), "duration")?;
+
+            let kind = match args.next() {
+                Some("fork") => PartitionKind::Fork,
+                arg => {
+                    let daemons = arg
+                        .into_iter()
+                        .chain(args)
+                        .map(|port| parse_arg::<u16>(Some(port), "daemon"))
+                        .collect::<Result<Vec<u16>, String>>()?;
+                    PartitionKind::Subset { daemons }
+                }
+            };
+
+            let mut ctx = ctx.lock().unwrap();
+            let known = ctx.lokid_ports().to_vec();
+            let mut ports = vec![daemon];
+            if let PartitionKind::Subset { daemons } = &kind {
+                ports.extend(daemons);
+            }
+            if let Some(port) = ports.iter().find(|port| !known.contains(port)) {
+                return Err(format!("no daemon on port {}", port));
+            }
+
+            ctx.start_partition(daemon, kind, std::time::Duration::from_millis(ms));
+        }
+        "swarms" => {
+            for swarm in ctx.lock().unwrap().get_swarms() {
+                println!("{:?}", swarm);
+            }
+        }
+        "history" => {
+            for block in ctx.lock().unwrap().get_history() {
+                println!(
+                    "{} {} (hf {}, {} swarms)",
+                    block.height,
+                    block.hash,
+                    block.hardfork,
+                    block.swarms.len()
+                );
+                for event in &block.events {
+                    println!("    {:?}", event);
+                }
+            }
+        }
+        "events" => {
+            let events = ctx.lock().unwrap().get_events();
+            let n = match args.next() {
+                Some(n) => parse_arg::<usize>(Some(n), "n")?,
+                None => events.len(),
+            };
+
+            for event in &events[events.len().saturating_sub(n)..] {
+                println!("{} {:?}", event.height, event.event);
+            }
+        }
+        "pings" => {
+            for (port, pings) in ctx.lock().unwrap().get_pings() {
+                let ping = &pings.last;
+                println!(
+                    "{}: {} pings, last {} ms after genesis via oxend {} (version {})",
+                    port,
+                    pings.count,
+                    ping.time_ms,
+                    ping.lokid_port,
+                    ping.version.as_deref().unwrap_or("unknown")
+                );
+            }
+        }
+        "owner" => {
+            let pk = PubKey::new(parse_pubkey(args.next())?).expect("pubkey already checked");
+
+            let ctx = ctx.lock().unwrap();
+            let height = match args.next() {
+                Some(height) => parse_arg::<u64>(Some(height), "height")?,
+                None => ctx.get_height(),
+            };
+
+            match ctx.swarm_for_pk_at(&pk, height) {
+                Some(swarm_id) => println!("swarm {} at height {}", swarm_id, height),
+                None => println!("no swarm at height {}", height),
+            }
+        }
+        "send" => {
+            require_swarms(ctx)?;
+
+            let pk = match args.next() {
+                Some(pk) => parse_pubkey(Some(pk))?,
+                None => {
+                    ctx.lock().unwrap().send_random_message();
+                    return Ok(true);
+                }
+            };
+
+            let msg = args.collect::<Vec<&str>>().join(" ");
+            if msg.is_empty() {
+                return Err("missing message".to_owned());
+            }
+
+            ctx.lock().unwrap().send_message(pk, &msg);
+        }
+        "check" => {
+            ctx.lock().unwrap().check_messages();
+        }
+        "test" => {
+            let swarms = ctx.lock().unwrap().get_swarms();
+
+            for s in &swarms {
+                println!("          ___swarm {}___", s.swarm_id);
+                print_sn_data(s);
+            }
+        }
+        _ => return Err(format!("unknown command: {} (try `help`)", command)),
+    }
+
+    Ok(true)
+}
+
+/// Read commands from stdin until `quit` (or the end of input)
+pub fn run(ctx: &Arc<Mutex<TestContext>>) {
+    println!("Type `help` for the list of commands");
+
+    let stdin = std::io::stdin();
+
+    let mut producer = None;
+
+    for line in stdin.lock().lines() {
+        let line = line.expect("could not read from stdin");
+
+        match execute(ctx, &mut producer, &line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    if let Some(producer) = producer {
+        producer.stop();
+    }
+
+    println!("terminating...");
+}
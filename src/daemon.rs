@@ -1,103 +1,298 @@
 
-use crate::swarms::Swarm;
-use crate::blockchain::Blockchain;
+use crate::blockchain::{self, Blockchain, BlockRecord, SyncPhase};
+use crate::config::DaemonConfig;
+use crate::faults::{self, FaultPhase, RpcFault};
+use crate::partition::{self, Partition};
+use crate::uptime::Ping;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often an idle view checks whether it has been dropped
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
 
 pub trait BlockchainViewable {
 
-    fn get_swarms(&self) -> Vec<Swarm>;
+    /// The block served right now, so that everything
+    /// in a response comes from the same block
+    fn get_tip(&self) -> BlockRecord;
 
-    fn get_height(&self) -> u64;
+    /// How many blocks behind the network this view reports itself
+    fn get_sync_lag(&self) -> u64;
 
-    fn get_block_hash(&self) -> String;
+    /// A past block, as long as this view has already seen it
+    fn get_block(&self, height: u64) -> Option<BlockRecord>;
 
-    fn get_target_height(&self) -> u64;
+    /// Pass on an uptime proof from a storage server
+    fn record_ping(&self, ping: Ping);
 
-    fn get_hf(&self) -> u8;
+    /// How requests are to be (mis)handled right now
+    fn rpc_fault(&self) -> Option<RpcFault>;
 
 }
 
-#[derive(Debug)]
+/// Everything this daemon has heard about the chain so far
+#[derive(Debug, Default)]
 struct BlockchainData {
-    swarms: Vec<Swarm>,
-    height: u64,
-    block_hash: String,
-    target_height: u64,
+    /// Every tip in the order it reached this daemon, along with when
+    /// (time since genesis). Blocks replaced by a reorg stay here, but
+    /// always come before the ones that replaced them.
+    blocks: Vec<(u64, BlockRecord)>,
+    genesis: Option<SystemTime>,
+    /// Periods during which all daemons are syncing
+    sync_phases: Vec<SyncPhase>,
+    partitions: Vec<Partition>,
+}
+
+impl BlockchainData {
+    fn elapsed_ms(&self) -> u64 {
+        self.genesis
+            .and_then(|genesis| genesis.elapsed().ok())
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
 }
 
 #[derive(Debug)]
 pub struct BlockchainView {
+    port: u16,
     cache : Arc<Mutex<BlockchainData>>,
+    bc: Arc<Mutex<Blockchain>>,
+    sync_phases: Vec<SyncPhase>,
+    faults: Vec<FaultPhase>,
+    /// Reported until the first block arrives
+    genesis_hf: u8,
+    stopped: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
 }
 
 impl Drop for BlockchainView {
 
     fn drop(&mut self) {
         println!("dropping BlockchainView");
+
+        // Stop listening to the blockchain
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
     }
 }
 
 impl BlockchainView {
-    pub fn new(bc: &Arc<Mutex<Blockchain>>, update_period: std::time::Duration) -> BlockchainView {
-        let cache = BlockchainData { swarms : vec![], height : 0, block_hash : String::new(), target_height: 0};
-        let cache = Arc::new(Mutex::new(cache));
+    /// New blocks reach this view `daemon.delay_ms` after they have been
+    /// produced. The daemon's sync phases and faults are specific to
+    /// this view, on top of those of the blockchain.
+    pub fn new(bc: &Arc<Mutex<Blockchain>>, daemon: &DaemonConfig) -> BlockchainView {
+        let cache = Arc::new(Mutex::new(BlockchainData::default()));
 
-        let bc = bc.clone();
+        let (updates, genesis_hf) = {
+            let mut bc = bc.lock().unwrap();
+            (bc.subscribe(), bc.history()[0].hardfork)
+        };
+        let delay = daemon.delay();
 
-        let cache_clone = cache.clone();
-        std::thread::spawn(move || {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopped_clone = stopped.clone();
 
-            loop {
+        let cache_clone = cache.clone();
+        let thread = std::thread::spawn(move || {
+
+            // Updates come in order, and all of them are delayed
+            // by the same amount, so waiting for each one in
+            // turn doesn't hold back the ones after it
+            while !stopped_clone.load(Ordering::SeqCst) {
+
+                let update = match updates.recv_timeout(STOP_CHECK_INTERVAL) {
+                    Ok(update) => update,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                let due = update.time + delay;
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    std::thread::sleep(wait);
+                }
 
-                let bc = bc.lock().unwrap();
                 let mut cache = cache_clone.lock().unwrap();
 
-                let hash = bc.get_block_hash().clone();
+                cache.genesis = Some(update.genesis);
 
-                if hash != cache.block_hash {
-
-                    cache.block_hash = hash;
-                    cache.swarms = bc.get_swarms();
-                    cache.height = bc.get_height();
-                    cache.target_height = bc.get_target_height();
+                let is_new = cache
+                    .blocks
+                    .last()
+                    .is_none_or(|(_, block)| block.hash != update.block.hash);
 
+                if is_new {
+                    let ms = cache.elapsed_ms();
+                    cache.blocks.push((ms, update.block));
                 }
 
-                drop(cache);
-                drop(bc);
-
-                std::thread::sleep(update_period);
+                cache.sync_phases = update.sync_phases;
+                cache.partitions = update.partitions;
             }
 
-
         });
 
-        BlockchainView { cache }
+        BlockchainView {
+            port: daemon.port,
+            cache,
+            bc: bc.clone(),
+            sync_phases: daemon.sync_phases.clone(),
+            faults: daemon.faults.clone(),
+            genesis_hf,
+            stopped,
+            thread: Some(thread),
+        }
+    }
+
+    /// The blocks this daemon knows about right now, the last one being
+    /// its tip, and how many blocks behind that tip it is serving
+    fn known_blocks(&self, cache: &BlockchainData) -> (usize, u64) {
+        let ms = cache.elapsed_ms();
+        let elapsed = Duration::from_millis(ms);
+
+        // A frozen daemon keeps serving whatever it had when it froze
+        let cutoff = faults::frozen_since(&self.faults, elapsed).unwrap_or(ms);
+        let known = cache.blocks.iter().take_while(|(at, _)| *at <= cutoff).count();
+
+        let stale = faults::stale_blocks(&faults::active_faults(&self.faults, elapsed));
+
+        (known, stale)
+    }
+
+    /// The latest block known at `height`, i.e. not one replaced by a reorg
+    fn find_block(blocks: &[(u64, BlockRecord)], height: u64) -> Option<&BlockRecord> {
+        blocks
+            .iter()
+            .rev()
+            .map(|(_, block)| block)
+            .find(|block| block.height == height)
+    }
+
+    /// The block this daemon serves right now
+    fn tip(&self) -> Option<BlockRecord> {
+        let cache = self.cache.lock().unwrap();
+        let (known, stale) = self.known_blocks(&cache);
+        let blocks = &cache.blocks[..known];
+
+        let (_, tip) = blocks.last()?;
+        let height = tip.height.saturating_sub(stale);
+
+        let block = BlockchainView::find_block(blocks, height)
+            .or_else(|| blocks.first().map(|(_, block)| block))?;
+
+        Some(self.as_seen(&cache, blocks, block))
+    }
+
+    /// `block` as this daemon sees it, which is
+    /// only different while it is partitioned
+    fn as_seen(
+        &self,
+        cache: &BlockchainData,
+        blocks: &[(u64, BlockRecord)],
+        block: &BlockRecord,
+    ) -> BlockRecord {
+        let elapsed = Duration::from_millis(cache.elapsed_ms());
+
+        match partition::active_partition(&cache.partitions, self.port, elapsed) {
+            Some(partition) => {
+                let split = blocks
+                    .iter()
+                    .take_while(|(at, _)| *at <= partition.start_ms)
+                    .last()
+                    .map(|(_, block)| block);
+
+                partition::partitioned_block(partition, block, split)
+            }
+            None => block.clone(),
+        }
     }
 }
 
 impl BlockchainViewable for BlockchainView {
 
-    fn get_swarms(&self) -> Vec<Swarm> {
-        self.cache.lock().unwrap().swarms.clone()
+    fn get_tip(&self) -> BlockRecord {
+        self.tip().unwrap_or_else(|| BlockRecord {
+            hardfork: self.genesis_hf,
+            ..BlockRecord::default()
+        })
+    }
+
+    /// A stale daemon doesn't know that it is behind, so
+    /// this is on top of the (stale) tip it serves
+    fn get_sync_lag(&self) -> u64 {
+        let cache = self.cache.lock().unwrap();
+        let elapsed = Duration::from_millis(cache.elapsed_ms());
+
+        std::cmp::max(
+            blockchain::sync_lag(&cache.sync_phases, elapsed),
+            blockchain::sync_lag(&self.sync_phases, elapsed),
+        )
+    }
+
+    fn get_block(&self, height: u64) -> Option<BlockRecord> {
+        if height > self.get_tip().height {
+            return None;
+        }
+
+        let cache = self.cache.lock().unwrap();
+        let (known, _) = self.known_blocks(&cache);
+        let blocks = &cache.blocks[..known];
+
+        BlockchainView::find_block(blocks, height).map(|block| self.as_seen(&cache, blocks, block))
+    }
+
+    fn record_ping(&self, ping: Ping) {
+        self.bc.lock().unwrap().record_ping(ping);
+    }
+
+    fn rpc_fault(&self) -> Option<RpcFault> {
+        if self.faults.is_empty() {
+            return None;
+        }
+
+        let elapsed = Duration::from_millis(self.cache.lock().unwrap().elapsed_ms());
+        faults::rpc_fault(&faults::active_faults(&self.faults, elapsed))
+    }
+
+}
+
+/// A fixed chain with no blockchain or thread behind it, so that
+/// the RPC layer can be tested in isolation
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct StaticView {
+    /// Every block this view has seen, the last one being its tip
+    pub blocks: Vec<BlockRecord>,
+    /// How far behind the network this view reports itself
+    pub sync_lag: u64,
+    pub fault: Option<RpcFault>,
+    /// Uptime proofs passed on so far
+    pub pings: Mutex<Vec<Ping>>,
+}
+
+#[cfg(test)]
+impl BlockchainViewable for StaticView {
+
+    fn get_tip(&self) -> BlockRecord {
+        self.blocks.last().cloned().unwrap_or_default()
     }
 
-    fn get_height(&self) -> u64 {
-        self.cache.lock().unwrap().height
+    fn get_sync_lag(&self) -> u64 {
+        self.sync_lag
     }
 
-    fn get_block_hash(&self) -> String {
-        self.cache.lock().unwrap().block_hash.clone()
+    fn get_block(&self, height: u64) -> Option<BlockRecord> {
+        self.blocks.iter().rev().find(|block| block.height == height).cloned()
     }
 
-    fn get_target_height(&self) -> u64 {
-        self.cache.lock().unwrap().target_height
+    fn record_ping(&self, ping: Ping) {
+        self.pings.lock().unwrap().push(ping);
     }
 
-    fn get_hf(&self) -> u8 {
-        // we start with height 20...
-        15
-        // if self.cache.lock().unwrap().height < 23 { 14 } else { 15 }
+    fn rpc_fault(&self) -> Option<RpcFault> {
+        self.fault
     }
 
 }
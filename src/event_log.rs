@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Something that happened to a service node or a swarm
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event")]
+pub enum NetworkEvent {
+    Registered { port: String, pubkey: String, swarm_id: u64 },
+    Deregistered { port: String, pubkey: String, swarm_id: u64 },
+    MovedBetweenSwarms { port: String, from: u64, to: u64 },
+    SwarmCreated { swarm_id: u64 },
+    SwarmDissolved { swarm_id: u64 },
+    /// The server was shut down, but the node is still registered
+    Disconnected { port: String },
+    /// The server of a disconnected node was started again
+    Restored { port: String },
+    /// Registered without being fully staked, so not part of any swarm yet
+    AwaitingContributions { port: String, pubkey: String },
+    Decommissioned { port: String, credit_blocks: u64 },
+    Recommissioned { port: String },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LoggedEvent {
+    /// Height of the current block when the event happened,
+    /// i.e. swarm changes only take effect at the next height
+    pub height: u64,
+    /// Unix time in milliseconds
+    pub time_ms: u64,
+    #[serde(flatten)]
+    pub event: NetworkEvent,
+}
+
+/// All network events, also written out as JSON Lines as they happen,
+/// so that they can be lined up with the storage server logs
+pub struct EventLog {
+    height: u64,
+    events: Vec<LoggedEvent>,
+    file: Option<File>,
+}
+
+impl EventLog {
+    pub fn new(path: &Path) -> EventLog {
+        let file = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| File::create(path));
+
+        let file = match file {
+            Ok(file) => Some(file),
+            Err(e) => {
+                error!("could not create event log {}: {}", path.display(), e);
+                None
+            }
+        };
+
+        EventLog {
+            height: 0,
+            events: vec![],
+            file,
+        }
+    }
+
+    /// Called by the blockchain whenever the current block changes
+    pub fn set_height(&mut self, height: u64) {
+        self.height = height;
+    }
+
+    pub fn record(&mut self, event: NetworkEvent) {
+        let time_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        let event = LoggedEvent {
+            height: self.height,
+            time_ms,
+            event,
+        };
+
+        info!("event at height {}: {:?}", event.height, event.event);
+
+        if let Some(file) = &mut self.file {
+            let line = serde_json::to_string(&event).expect("could not construct json");
+            if let Err(e) = writeln!(file, "{}", line) {
+                error!("could not write to event log: {}", e);
+            }
+        }
+
+        self.events.push(event);
+    }
+
+    pub fn events(&self) -> &[LoggedEvent] {
+        &self.events
+    }
+}
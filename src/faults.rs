@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+/// Something wrong with a single daemon
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Fault {
+    /// Stop picking up new blocks
+    Freeze,
+    /// Answer every request with an error
+    Error,
+    /// Hold every request for `delay_ms` before answering it
+    Timeout { delay_ms: u64 },
+    /// Serve the chain as of `blocks` blocks ago, without knowing it is behind
+    Lag { blocks: u64 },
+    /// Reachable for `up_ms`, then unreachable for `down_ms`, and so on
+    Flap { up_ms: u64, down_ms: u64 },
+}
+
+/// `fault` lasts for `duration_ms`, starting `start_ms` after genesis
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaultPhase {
+    pub start_ms: u64,
+    pub duration_ms: u64,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+/// What the RPC server of a faulty daemon does with a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcFault {
+    Unreachable,
+    Error,
+    Delay(Duration),
+}
+
+/// Faults in effect `elapsed` after genesis
+pub fn active_faults(phases: &[FaultPhase], elapsed: Duration) -> Vec<Fault> {
+    let ms = elapsed.as_millis() as u64;
+
+    phases
+        .iter()
+        .filter(|phase| phase.start_ms <= ms && ms < phase.start_ms + phase.duration_ms)
+        .filter(|phase| match phase.fault {
+            // Flapping daemons start out reachable
+            Fault::Flap { up_ms, down_ms } => (ms - phase.start_ms) % (up_ms + down_ms) >= up_ms,
+            _ => true,
+        })
+        .map(|phase| phase.fault)
+        .collect()
+}
+
+/// When the freeze in effect `elapsed` after genesis started, if any
+pub fn frozen_since(phases: &[FaultPhase], elapsed: Duration) -> Option<u64> {
+    let ms = elapsed.as_millis() as u64;
+
+    phases
+        .iter()
+        .filter(|phase| phase.fault == Fault::Freeze)
+        .filter(|phase| phase.start_ms <= ms && ms < phase.start_ms + phase.duration_ms)
+        .map(|phase| phase.start_ms)
+        .min()
+}
+
+/// How many blocks behind the chain a daemon with `faults` is
+pub fn stale_blocks(faults: &[Fault]) -> u64 {
+    faults
+        .iter()
+        .filter_map(|fault| match fault {
+            Fault::Lag { blocks } => Some(*blocks),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// The worst of `faults` as far as requests are concerned
+pub fn rpc_fault(faults: &[Fault]) -> Option<RpcFault> {
+    let delay = faults
+        .iter()
+        .filter_map(|fault| match fault {
+            Fault::Timeout { delay_ms } => Some(*delay_ms),
+            _ => None,
+        })
+        .max();
+
+    if faults.iter().any(|fault| matches!(fault, Fault::Flap { .. })) {
+        Some(RpcFault::Unreachable)
+    } else if faults.contains(&Fault::Error) {
+        Some(RpcFault::Error)
+    } else {
+        delay.map(|ms| RpcFault::Delay(Duration::from_millis(ms)))
+    }
+}
@@ -0,0 +1,252 @@
+use crate::blockchain::{Blockchain, ChainParams};
+use crate::config::{DaemonConfig, HarnessConfig};
+use crate::daemon::BlockchainView;
+use crate::rpc_server::{self, RpcServer};
+use crate::test_context::{MessageReport, TestContext};
+use crate::tests::{Scenario, TestOptions};
+use crate::timeline::{self, Timeline};
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    /// The scenario finished, but doesn't check messages
+    Unverified,
+    Panicked,
+}
+
+impl Outcome {
+    pub fn is_failure(self) -> bool {
+        self == Outcome::Failed || self == Outcome::Panicked
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Passed => "PASS",
+            Outcome::Failed => "FAIL",
+            Outcome::Unverified => "n/a",
+            Outcome::Panicked => "PANIC",
+        }
+    }
+}
+
+pub struct ScenarioRun {
+    pub name: &'static str,
+    pub outcome: Outcome,
+    pub report: Option<MessageReport>,
+    pub elapsed: Duration,
+    /// Number of swarms dissolved during the scenario
+    pub dissolved: u64,
+}
+
+/// Owns the simulated blockchain and its oxend stand-ins,
+/// and sets up a clean network for every scenario
+pub struct Harness {
+    /// The chain of the current scenario, created by `reset`
+    blockchain: Mutex<Option<Arc<Mutex<Blockchain>>>>,
+    /// The context of the current scenario, stopped by the next `reset`
+    context: Mutex<Option<Arc<Mutex<TestContext>>>>,
+    bin_path: String,
+    lokid_ports: Vec<u16>,
+    playground: PathBuf,
+    seed: u64,
+    chain_params: ChainParams,
+    daemons: Vec<DaemonConfig>,
+    /// The RPC servers of the oxend stand-ins, running from `reset` on
+    servers: Mutex<Vec<RpcServer>>,
+}
+
+impl Harness {
+    pub fn new(bin_path: &str, config: &HarnessConfig, playground: &Path, seed: u64) -> Harness {
+        Harness {
+            blockchain: Mutex::new(None),
+            context: Mutex::new(None),
+            bin_path: bin_path.to_owned(),
+            lokid_ports: config.lokid_ports(),
+            playground: playground.to_owned(),
+            seed,
+            chain_params: config.chain_params(),
+            daemons: config.daemons.clone(),
+            servers: Mutex::new(vec![]),
+        }
+    }
+
+    /// Create a view into the blockchain for every daemon,
+    /// each of them served by an RPC server of its own
+    fn start_daemons(&self, blockchain: &Arc<Mutex<Blockchain>>) {
+        let mut servers = self.servers.lock().unwrap();
+
+        for daemon in &self.daemons {
+            let view = BlockchainView::new(blockchain, daemon);
+            let server = rpc_server::start_http_server2(view, daemon.port)
+                .unwrap_or_else(|e| panic!("{}", e));
+            servers.push(server);
+        }
+    }
+
+    /// Stop the daemons, releasing their ports
+    fn stop_daemons(&self) {
+        for server in self.servers.lock().unwrap().drain(..) {
+            server.stop();
+        }
+    }
+
+    /// Stop the daemons and all service nodes
+    pub fn shutdown(&self) {
+        self.stop_daemons();
+        self.stop_scenario();
+    }
+
+    /// Stop the threads of the current scenario, then its service nodes,
+    /// so that none of them is restarted behind our back
+    fn stop_scenario(&self) {
+        if let Some(ctx) = self.context.lock().unwrap().take() {
+            ctx.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .stop();
+        }
+
+        self.quit_service_nodes();
+    }
+
+    /// Stop the service nodes of the current scenario, if any
+    pub fn quit_service_nodes(&self) {
+        if let Some(blockchain) = self.current_blockchain() {
+            lock_blockchain(&blockchain).swarm_manager.quit_children();
+        }
+    }
+
+    fn current_blockchain(&self) -> Option<Arc<Mutex<Blockchain>>> {
+        self.blockchain.lock().unwrap().clone()
+    }
+
+    /// Shut down all service nodes and daemons from the previous scenario
+    /// and start over with an empty blockchain in `playground/<scenario>`,
+    /// served by fresh daemons. Every scenario starts from the same seed,
+    /// so it can be reproduced on its own.
+    pub fn reset(&self, scenario: &str) -> Arc<Mutex<TestContext>> {
+        // Their requests may need the blockchain
+        self.stop_daemons();
+        self.stop_scenario();
+
+        let path = self.playground.join(scenario);
+        if path.exists() {
+            std::fs::remove_dir_all(&path).expect("could not remove scenario playground");
+        }
+
+        let blockchain = Blockchain::new(&self.bin_path, &path, self.seed, &self.chain_params);
+        let blockchain = Arc::new(Mutex::new(blockchain));
+        *self.blockchain.lock().unwrap() = Some(Arc::clone(&blockchain));
+
+        self.start_daemons(&blockchain);
+
+        let ctx = TestContext::new(blockchain, &self.lokid_ports, self.seed);
+        let ctx = Arc::new(Mutex::new(ctx));
+        *self.context.lock().unwrap() = Some(Arc::clone(&ctx));
+
+        ctx
+    }
+
+    /// Run `scenario` on a network freshly created with `reset`. The service
+    /// nodes are left running, so they can still be inspected afterwards.
+    pub fn run(
+        &self,
+        ctx: &Arc<Mutex<TestContext>>,
+        scenario: &Scenario,
+        options: &TestOptions,
+    ) -> ScenarioRun {
+        info!("running scenario {} with {:?}", scenario.name, options);
+
+        self.run_with(scenario.name, || (scenario.run)(ctx, options))
+    }
+
+    /// Re-execute a recorded run on a network freshly created with `reset`
+    pub fn replay(&self, ctx: &Arc<Mutex<TestContext>>, timeline: &Timeline) -> ScenarioRun {
+        info!("replaying a timeline recorded with seed {}", timeline.seed);
+
+        self.run_with("replay", || timeline::replay(ctx, timeline))
+    }
+
+    fn run_with<F>(&self, name: &'static str, run: F) -> ScenarioRun
+    where
+        F: FnOnce() -> Option<MessageReport>,
+    {
+        println!("=== {} ===", name);
+
+        let start = Instant::now();
+
+        let verdict = std::panic::catch_unwind(std::panic::AssertUnwindSafe(run));
+
+        let (outcome, report) = match verdict {
+            Ok(Some(report)) => {
+                let outcome = if report.passed() {
+                    Outcome::Passed
+                } else {
+                    Outcome::Failed
+                };
+                (outcome, Some(report))
+            }
+            Ok(None) => (Outcome::Unverified, None),
+            Err(_) => {
+                error!("scenario {} panicked", name);
+                (Outcome::Panicked, None)
+            }
+        };
+
+        let elapsed = start.elapsed();
+        let dissolved = self
+            .current_blockchain()
+            .map_or(0, |blockchain| lock_blockchain(&blockchain).swarm_manager.stats.dissolved);
+
+        ScenarioRun {
+            name,
+            outcome,
+            report,
+            elapsed,
+            dissolved,
+        }
+    }
+}
+
+fn lock_blockchain(blockchain: &Mutex<Blockchain>) -> MutexGuard<'_, Blockchain> {
+    // A panicking scenario may leave the lock poisoned,
+    // which shouldn't prevent us from cleaning up after it
+    blockchain
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn print_summary(runs: &[ScenarioRun]) {
+    println!();
+    println!(
+        "{:<36} {:<6} {:>8} {:>16}",
+        "scenario", "result", "time", "lost/tested"
+    );
+
+    for run in runs {
+        let messages = match &run.report {
+            Some(report) => format!("{}/{}", report.lost, report.tested),
+            None => "-".to_owned(),
+        };
+
+        println!(
+            "{:<36} {:<6} {:>7}s {:>16}",
+            run.name,
+            run.outcome.label(),
+            run.elapsed.as_secs(),
+            messages
+        );
+    }
+
+    let failed = runs.iter().filter(|r| r.outcome.is_failure()).count();
+
+    if failed == 0 {
+        println!("Suite passed ({} scenarios)", runs.len());
+    } else {
+        println!("Suite failed: {}/{} scenarios", failed, runs.len());
+    }
+}
@@ -8,38 +8,37 @@ extern crate log;
 extern crate env_logger;
 extern crate log4rs;
 
+mod block_producer;
 mod blockchain;
 mod client;
+mod config;
+mod console;
 mod daemon;
+mod event_log;
+mod faults;
+mod harness;
+mod partition;
+mod report;
 mod rpc_server;
+mod run_dir;
+mod seed;
 mod service_node;
 mod swarms;
 mod test_context;
 mod tests;
+mod timeline;
+mod uptime;
 
 use rand::prelude::*;
-use std::io::prelude::*;
-use std::time::Duration;
-use swarms::*;
 
 use service_node::ServiceNode;
 
-use blockchain::Blockchain;
-use daemon::BlockchainView;
-use std::sync::{Arc, Mutex};
-use test_context::TestContext;
-
-fn print_sn_data(swarm: &Swarm) {
-    for sn in &swarm.nodes {
-        println!("[{}]", &sn.port);
-
-        let messages = client::request_all_messages(&sn.port);
-
-        for msg in messages {
-            println!("  {} {}", msg.pk, msg.data);
-        }
-    }
-}
+use config::HarnessConfig;
+use harness::Harness;
+use run_dir::RunDir;
+use timeline::Timeline;
+use std::path::Path;
+use std::sync::Arc;
 
 fn send_req_to_quit(sn: &ServiceNode) -> Result<(), ()> {
     let target = "/quit/v1";
@@ -60,160 +59,328 @@ fn send_req_to_quit(sn: &ServiceNode) -> Result<(), ()> {
     }
 }
 
-fn gracefully_exit(bc: &Arc<Mutex<Blockchain>>) {
-    let sm = &mut bc.lock().unwrap().swarm_manager;
+fn gracefully_exit(harness: &Harness, code: i32) {
+    harness.shutdown();
 
-    sm.quit_children();
+    std::process::exit(code);
+}
 
-    // Not sure how to stop rpc the server,
-    // or whether that is even necessary
-    std::process::exit(0);
+fn exit_with_error(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1);
 }
 
-fn from_mins(mins: u64) -> std::time::Duration {
-    let secs = mins * 60;
-    std::time::Duration::from_secs(secs)
+fn print_scenarios() {
+    for scenario in tests::SCENARIOS {
+        println!("{:<36} {}", scenario.name, scenario.description);
+    }
+}
+
+fn init_logging(log_dir: &Path) {
+    use log4rs::append::file::FileAppender;
+    use log4rs::config::{Appender, Config, Root};
+    use log4rs::encode::pattern::PatternEncoder;
+
+    let tests = FileAppender::builder()
+        .encoder(Box::new(PatternEncoder::new("{d}: [{l}] {m}{n}")))
+        .build(log_dir.join("tests.log"))
+        .expect("could not create log file");
+
+    let config = Config::builder()
+        .appender(Appender::builder().build("tests", Box::new(tests)))
+        .build(Root::builder().appender("tests").build(log::LevelFilter::Info))
+        .expect("invalid log config");
+
+    log4rs::init_config(config).expect("could not initialize logging");
+}
+
+fn write_reports(
+    matches: &clap::ArgMatches,
+    run_dir: &RunDir,
+    bin_path: &str,
+    seed: u64,
+    runs: &[harness::ScenarioRun],
+) {
+    let report = report::RunReport::new(bin_path, seed, runs);
+
+    let path = run_dir.report_path();
+    if let Err(e) = std::fs::write(&path, report.to_json()) {
+        error!("could not write report to {}: {}", path.display(), e);
+    }
+
+    if let Some(path) = matches.value_of("junit") {
+        let xml = report.to_junit(matches.is_present("report-detail"));
+        if let Err(e) = std::fs::write(path, xml) {
+            error!("could not write JUnit report to {}: {}", path, e);
+        }
+    }
+
+    if let Some(path) = matches.value_of("json-report") {
+        if let Err(e) = std::fs::write(path, report.to_json()) {
+            error!("could not write JSON report to {}: {}", path, e);
+        }
+    }
+}
+
+fn binary_path_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("binary-path")
+        .help("Path to the Storage Server binary to test")
+        .takes_value(true)
+        .required(true)
 }
 
 fn main() {
+    let scenario_names: Vec<&str> = tests::SCENARIOS.iter().map(|s| s.name).collect();
+
     let matches = clap::App::new("Migration Testing")
         .version("0.1")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            clap::Arg::with_name("config")
+                .long("config")
+                .help("TOML file with harness settings and test option profiles")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("profile")
+                .long("profile")
+                .help("Name of the profile from the config file to take test options from")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("seed")
+                .long("seed")
+                .help("Seed for every random choice made by the harness (random if not set)")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("junit")
+                .long("junit")
+                .help("Write the results in JUnit XML format to this file")
+                .takes_value(true)
+                .global(true),
+        )
         .arg(
-            clap::Arg::with_name("binary-path")
-                .help("Path to the Storage Server binary to test")
+            clap::Arg::with_name("json-report")
+                .long("json-report")
+                .help("Write the results in JSON format to this file")
                 .takes_value(true)
-                .required(true),
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("keep-runs")
+                .long("keep-runs")
+                .help("Number of run directories to keep in `runs/`, including this one (0 keeps all)")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("report-detail")
+                .long("report-detail")
+                .help("Add a JUnit test case for every checked pubkey and swarm")
+                .global(true),
+        )
+        .subcommand(clap::SubCommand::with_name("list").about("List available test scenarios"))
+        .subcommand(
+            clap::SubCommand::with_name("run")
+                .about("Run a single test scenario")
+                .arg(
+                    clap::Arg::with_name("scenario")
+                        .help("Name of the scenario to run (see `list`)")
+                        .possible_values(&scenario_names)
+                        .hide_possible_values(true)
+                        .required(true),
+                )
+                .arg(binary_path_arg())
+                .arg(
+                    clap::Arg::with_name("headless")
+                        .long("headless")
+                        .alias("non-interactive")
+                        .help("Shut down as soon as the scenario is finished instead of opening the console, exiting with a non-zero code if any messages were lost"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("replay")
+                .about("Re-execute a recorded run against a fresh network, with the same timing")
+                .arg(
+                    clap::Arg::with_name("timeline")
+                        .help("Timeline of the run to replay (`playground/<scenario>/timeline.jsonl` in its run directory)")
+                        .required(true),
+                )
+                .arg(binary_path_arg())
+                .arg(
+                    clap::Arg::with_name("headless")
+                        .long("headless")
+                        .alias("non-interactive")
+                        .help("Shut down as soon as the replay is finished instead of opening the console, exiting with a non-zero code if any messages were lost"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("suite")
+                .about("Run several test scenarios one after another, each on a fresh network")
+                .arg(binary_path_arg())
+                .arg(
+                    clap::Arg::with_name("scenarios")
+                        .help("Names of the scenarios to run (see `list`)")
+                        .possible_values(&scenario_names)
+                        .hide_possible_values(true)
+                        .multiple(true)
+                        .required(true),
+                ),
         )
         .get_matches();
 
+    let (command, matches) = match matches.subcommand() {
+        ("list", _) | (_, None) => {
+            print_scenarios();
+            return;
+        }
+        (command, Some(sub_matches)) => (command, sub_matches),
+    };
+
+    // Replays don't run any scenario
+    let scenarios: Vec<&tests::Scenario> = matches
+        .values_of("scenario")
+        .or_else(|| matches.values_of("scenarios"))
+        .map(|names| {
+            names
+                .map(|name| tests::find_scenario(name).expect("unknown scenario"))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let timeline = matches
+        .value_of("timeline")
+        .map(|path| Timeline::load(path).unwrap_or_else(|e| exit_with_error(&e)));
+
     let bin_path = matches
         .value_of("binary-path")
         .expect("no value for binary-path");
     println!("Using path: {}", bin_path);
 
-    // Overwrite logs with every run
-    let path = std::path::Path::new("log");
-    if path.exists() {
-        std::fs::remove_dir_all("log").expect("could not remove 'log' directory");
-    }
-
-    log4rs::init_file("config/log4rs.yaml", Default::default()).unwrap();
+    let mut config = match matches.value_of("config") {
+        Some(path) => HarnessConfig::load(path).unwrap_or_else(|e| exit_with_error(&e)),
+        None => HarnessConfig::default(),
+    };
 
-    let path = std::path::Path::new("playground");
-    if path.exists() {
-        std::fs::remove_dir_all(&path).expect("Could not remove existing 'playground' directory");
+    // A replay has to start from the same chain as the recording
+    if let Some(timeline) = &timeline {
+        config.hardforks = timeline.chain_params.hardforks.clone();
+        config.sync_phases = timeline.chain_params.sync_phases.clone();
+        config.partitions = timeline.chain_params.partitions.clone();
+        config.liveness = timeline.chain_params.liveness;
+        config.daemons = timeline.chain_params.daemons.clone();
     }
 
-    let blockchain = Blockchain::new(&bin_path);
-    let blockchain = Arc::new(Mutex::new(blockchain));
+    let seed = match (&timeline, matches.value_of("seed")) {
+        (Some(timeline), _) => timeline.seed,
+        (None, Some(seed)) => seed
+            .parse::<u64>()
+            .unwrap_or_else(|_| exit_with_error(&format!("invalid seed: {}", seed))),
+        (None, None) => config.seed.unwrap_or_else(|| rand::thread_rng().gen()),
+    };
+    println!("Using seed: {}", seed);
+
+    // Resolve options upfront so that we don't find
+    // a typo in the middle of a suite
+    let options: Vec<tests::TestOptions> = scenarios
+        .iter()
+        .map(|scenario| {
+            config
+                .test_options(scenario.name, matches.value_of("profile"))
+                .unwrap_or_else(|e| exit_with_error(&e))
+        })
+        .collect();
+
+    let keep_runs = match matches.value_of("keep-runs") {
+        Some(keep) => keep
+            .parse::<usize>()
+            .unwrap_or_else(|_| exit_with_error(&format!("invalid number of runs: {}", keep))),
+        None => config.keep_runs,
+    };
 
-    // multiple servers to simulate block progatation
-    let lokid_ports = [22129, 22139, 22149];
-    let update_period = [
-        Duration::from_millis(100),
-        Duration::from_millis(300),
-        Duration::from_millis(500),
-    ];
+    // Keep the logs and storage server data of past
+    // runs around, so that failures can be investigated
+    let label = match command {
+        "suite" | "replay" => command,
+        _ => scenarios[0].name,
+    };
+    let run_dir = RunDir::create(Path::new("runs"), label, seed, keep_runs)
+        .unwrap_or_else(|e| exit_with_error(&format!("could not create run directory: {}", e)));
+    println!("Using run directory: {}", run_dir.root().display());
 
-    assert_eq!(lokid_ports.len(), update_period.len());
+    init_logging(&run_dir.log_dir());
 
-    let ctx = TestContext::new(Arc::clone(&blockchain), &lokid_ports);
-    let ctx = Arc::new(Mutex::new(ctx));
+    info!("using seed: {}", seed);
 
-    // Create multiple views into the blockchain and create different
-    // server instances for each of them
+    let harness = Arc::new(Harness::new(bin_path, &config, &run_dir.playground(), seed));
 
-    for (port, period) in lokid_ports.iter().zip(update_period.iter()) {
-        let view = BlockchainView::new(&blockchain, *period);
-        let _ = rpc_server::start_http_server2(view, *port);
-    }
-
-    let bc = Arc::clone(&blockchain);
+    let harness_clone = Arc::clone(&harness);
 
     // Handle Ctrl+C
     ctrlc::set_handler(move || {
         println!(""); // go to next line
-        gracefully_exit(&bc);
+        gracefully_exit(&harness_clone, 1);
     })
     .expect("error handling Ctrl+C handler");
 
-    // Note: currently the test works fine to 15 minutes
-
-    let options = tests::TestOptions {
-        reliable_snodes: true,
-        duration: std::time::Duration::from_secs(40),
-        block_interval: std::time::Duration::from_secs(2),
-        message_interval: std::time::Duration::from_millis(200),
-    };
-
-    let _long_test_opt = tests::TestOptions {
-        reliable_snodes: true,
-        duration: from_mins(1),
-        block_interval: std::time::Duration::from_secs(10),
-        message_interval: std::time::Duration::from_millis(50),
-    };
-
-    let messenger_test_opt = tests::TestOptions {
-        reliable_snodes: true,
-        duration: from_mins(5),
-        block_interval: std::time::Duration::from_secs(10),
-        message_interval: std::time::Duration::from_millis(50),
-    };
-
     // Note: some (small) tests don't work anymore because
     // the node thinks it is "inactive". Need to make sure
     // smarm changes are relative to block hashes, that should
     // take care of the problem.
 
-    // tests::async_test(&ctx);
-
-    // Note: when testing long-polling, need to
-    // modify client request to use the right header
-    // tests::long_polling(&ctx);
-
-    // tests::one_node_big_data(&ctx);
-    // tests::test_bootstrapping_peer_big_data(&ctx);
-    // tests::test_bootstrapping_swarm_big_data(&ctx);
-    // tests::single_node_one_message(&ctx);
-    // tests::single_swarm_one_message(&ctx);
-    // tests::sinlge_swarm_joined(&ctx);
-    // tests::multiple_swarms_static(&ctx);
-    // tests::test_dissolving(&ctx);
-    // tests::test_retry_batches(&ctx);
-    // tests::test_retry_singles(&ctx);
-    // tests::test_blocks(&ctx, &options);
-    // tests::test_persistent_blocks(&ctx, &options);
-
-    tests::test_real_messenger(&ctx, &options);
-
-    let stdin = std::io::stdin();
-    let mut iterator = stdin.lock().lines();
-
-    let mut rng = StdRng::seed_from_u64(0);
-    loop {
-        let command = iterator.next().unwrap().unwrap();
-
-        if command == "quit" || command == "q" {
-            println!("terminating...");
-            break;
+    if command == "suite" {
+        let runs: Vec<harness::ScenarioRun> = scenarios
+            .iter()
+            .zip(options.iter())
+            .map(|(scenario, options)| {
+                let ctx = harness.reset(scenario.name);
+                harness.run(&ctx, scenario, options)
+            })
+            .collect();
+
+        harness::print_summary(&runs);
+        write_reports(matches, &run_dir, bin_path, seed, &runs);
+
+        let failed = runs.iter().any(|r| r.outcome.is_failure());
+        gracefully_exit(&harness, if failed { 1 } else { 0 });
+    }
+
+    let (ctx, run) = match &timeline {
+        Some(timeline) => {
+            let ctx = harness.reset("replay");
+            let run = harness.replay(&ctx, timeline);
+            (ctx, run)
+        }
+        None => {
+            let ctx = harness.reset(scenarios[0].name);
+            let run = harness.run(&ctx, scenarios[0], &options[0]);
+            (ctx, run)
         }
+    };
 
-        if command == "test" {
-            let swarms = ctx.lock().unwrap().get_swarms();
+    let code = if run.outcome.is_failure() { 1 } else { 0 };
 
-            for s in &swarms {
-                println!("          ___swarm {}___", s.swarm_id);
-                print_sn_data(s);
-            }
-        }
+    let headless = matches.is_present("headless") || config.headless;
 
-        if command == "send" {
-            // For now: send a random message to a random PK
-            ctx.lock().unwrap().send_random_message();
-        }
+    // Nobody is going to look at the network anymore,
+    // so don't keep the storage servers around
+    if headless {
+        harness.quit_service_nodes();
     }
 
+    write_reports(matches, &run_dir, bin_path, seed, &[run]);
+
+    if headless {
+        gracefully_exit(&harness, code);
+    }
+
+    console::run(&ctx);
+
     println!("waiting for service nodes to finish");
 
-    gracefully_exit(&blockchain);
+    gracefully_exit(&harness, code);
 }
@@ -0,0 +1,87 @@
+use crate::blockchain::BlockRecord;
+use crate::service_node::ServiceNode;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+/// How a partitioned daemon sees the network
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PartitionKind {
+    /// Only nodes attached (through their `lokid_port`) to the partitioned
+    /// daemon itself or to one of `daemons` are part of the swarms
+    Subset { daemons: Vec<u16> },
+    /// New blocks keep coming, but on a fork of its own that
+    /// doesn't pick up any membership changes after the split
+    Fork,
+}
+
+/// The daemon on `daemon` is cut off from (part of) the network for
+/// `duration_ms`, starting `start_ms` after genesis. When it is over,
+/// the daemon goes back to the main chain.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    pub daemon: u16,
+    pub start_ms: u64,
+    pub duration_ms: u64,
+    #[serde(flatten)]
+    pub kind: PartitionKind,
+}
+
+/// The partition of `daemon` in effect `elapsed` after genesis, if any
+pub fn active_partition(partitions: &[Partition], daemon: u16, elapsed: Duration) -> Option<&Partition> {
+    let ms = elapsed.as_millis() as u64;
+
+    partitions
+        .iter()
+        .filter(|p| p.daemon == daemon)
+        .find(|p| p.start_ms <= ms && ms < p.start_ms + p.duration_ms)
+}
+
+/// Storage servers tell blocks apart by their hash, so every
+/// partitioned version of a block needs a hash of its own
+fn partitioned_hash(hash: &str, partition: &Partition) -> String {
+    let partition = serde_json::to_vec(partition).expect("could not serialize partition");
+
+    let mut hasher = Sha256::new();
+    hasher.input(hash.as_bytes());
+    hasher.input(&partition);
+
+    hex::encode(hasher.result())
+}
+
+/// `block` as seen from the partitioned daemon. `split` is the last
+/// block the daemon got before the partition started.
+pub fn partitioned_block(
+    partition: &Partition,
+    block: &BlockRecord,
+    split: Option<&BlockRecord>,
+) -> BlockRecord {
+    let mut block = block.clone();
+
+    match &partition.kind {
+        PartitionKind::Subset { daemons } => {
+            let reachable = |sn: &ServiceNode| {
+                sn.lokid_port == partition.daemon || daemons.contains(&sn.lokid_port)
+            };
+
+            for swarm in &mut block.swarms {
+                swarm.nodes.retain(|sn| reachable(sn));
+            }
+            block.swarms.retain(|swarm| !swarm.nodes.is_empty());
+            block.awaiting.retain(|sn| reachable(sn));
+        }
+        PartitionKind::Fork => match split {
+            Some(split) if block.height > split.height => {
+                block.swarms = split.swarms.clone();
+                block.awaiting = split.awaiting.clone();
+            }
+            // Blocks up to the split are shared with the main chain
+            _ => return block,
+        },
+    }
+
+    block.hash = partitioned_hash(&block.hash, partition);
+    block.events = vec![];
+
+    block
+}
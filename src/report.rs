@@ -0,0 +1,196 @@
+use crate::harness::{Outcome, ScenarioRun};
+use crate::test_context::{MessageCount, MessageReport};
+
+use std::fmt::Write;
+use std::time::SystemTime;
+
+#[derive(Serialize, Debug)]
+struct ScenarioResult<'a> {
+    name: &'a str,
+    outcome: Outcome,
+    elapsed_secs: f64,
+    dissolved: u64,
+    messages: Option<&'a MessageReport>,
+}
+
+/// Results of one or more scenarios, written out for CI in
+/// JUnit XML and/or JSON format
+#[derive(Serialize, Debug)]
+pub struct RunReport<'a> {
+    binary_path: &'a str,
+    seed: u64,
+    /// Unix time in seconds
+    finished_at: u64,
+    scenarios: Vec<ScenarioResult<'a>>,
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&apos;"),
+            c => res.push(c),
+        }
+    }
+
+    res
+}
+
+/// A test case for a single pubkey or swarm from a message report
+fn write_count_case(xml: &mut String, classname: &str, name: &str, count: &MessageCount) {
+    let _ = write!(
+        xml,
+        "  <testcase classname=\"{}\" name=\"{}\" time=\"0\">",
+        escape_xml(classname),
+        escape_xml(name)
+    );
+
+    if count.lost > 0 {
+        let _ = write!(
+            xml,
+            "<failure message=\"{}/{} messages lost\"/>",
+            count.lost, count.tested
+        );
+    }
+
+    xml.push_str("</testcase>\n");
+}
+
+impl<'a> RunReport<'a> {
+    pub fn new(binary_path: &'a str, seed: u64, runs: &'a [ScenarioRun]) -> RunReport<'a> {
+        let finished_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let scenarios = runs
+            .iter()
+            .map(|run| ScenarioResult {
+                name: run.name,
+                outcome: run.outcome,
+                elapsed_secs: run.elapsed.as_secs_f64(),
+                dissolved: run.dissolved,
+                messages: run.report.as_ref(),
+            })
+            .collect();
+
+        RunReport {
+            binary_path,
+            seed,
+            finished_at,
+            scenarios,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self).expect("could not construct json")
+    }
+
+    /// One test case per scenario. With `detailed`, every checked
+    /// pubkey and swarm gets a test case of its own as well.
+    pub fn to_junit(&self, detailed: bool) -> String {
+        let mut cases = String::new();
+        let (mut tests, mut failures, mut errors) = (0, 0, 0);
+        let mut time = 0.0;
+
+        for scenario in &self.scenarios {
+            tests += 1;
+            time += scenario.elapsed_secs;
+
+            let _ = writeln!(
+                cases,
+                "  <testcase classname=\"migration_tests\" name=\"{}\" time=\"{:.3}\">",
+                escape_xml(scenario.name),
+                scenario.elapsed_secs
+            );
+
+            match (scenario.outcome, scenario.messages) {
+                (Outcome::Failed, Some(report)) => {
+                    failures += 1;
+
+                    let mut details = String::new();
+                    for lost in &report.lost_messages {
+                        let _ = writeln!(
+                            details,
+                            "pk {} (swarm {}): node {} is missing <{}> (sent at height {})",
+                            lost.pubkey, lost.swarm_id, lost.node, lost.data, lost.sent_height
+                        );
+                    }
+
+                    for check in &report.failed_checks {
+                        let _ = writeln!(details, "check failed: {}", check);
+                    }
+
+                    let _ = writeln!(
+                        cases,
+                        "    <failure message=\"{}/{} messages lost, {} checks failed\">{}</failure>",
+                        report.lost,
+                        report.tested,
+                        report.failed_checks.len(),
+                        escape_xml(&details)
+                    );
+                }
+                (Outcome::Panicked, _) => {
+                    errors += 1;
+                    cases.push_str("    <error message=\"scenario panicked\"/>\n");
+                }
+                _ => {}
+            }
+
+            let mut out = format!("dissolved swarms: {}\n", scenario.dissolved);
+            match scenario.messages {
+                Some(report) => {
+                    let _ = writeln!(out, "messages lost: {}/{}", report.lost, report.tested);
+                }
+                None => out.push_str("messages not checked\n"),
+            }
+            let _ = writeln!(cases, "    <system-out>{}</system-out>", escape_xml(&out));
+
+            cases.push_str("  </testcase>\n");
+
+            if let (true, Some(report)) = (detailed, scenario.messages) {
+                let classname = format!("{}.pubkey", scenario.name);
+                for (pk, count) in &report.per_pubkey {
+                    write_count_case(&mut cases, &classname, pk, count);
+                }
+
+                let classname = format!("{}.swarm", scenario.name);
+                for (swarm_id, count) in &report.per_swarm {
+                    write_count_case(&mut cases, &classname, &swarm_id.to_string(), count);
+                }
+
+                tests += report.per_pubkey.len() + report.per_swarm.len();
+                failures += report
+                    .per_pubkey
+                    .values()
+                    .chain(report.per_swarm.values())
+                    .filter(|count| count.lost > 0)
+                    .count();
+            }
+        }
+
+        let mut xml = String::new();
+
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<testsuite name=\"migration_tests\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+            tests, failures, errors, time
+        );
+        let _ = writeln!(
+            xml,
+            "  <properties><property name=\"binary_path\" value=\"{}\"/><property name=\"seed\" value=\"{}\"/></properties>",
+            escape_xml(self.binary_path),
+            self.seed
+        );
+        xml.push_str(&cases);
+        xml.push_str("</testsuite>\n");
+
+        xml
+    }
+}
@@ -1,4 +1,14 @@
-use crate::daemon::{BlockchainView, BlockchainViewable};
+use crate::daemon::BlockchainViewable;
+use crate::faults::RpcFault;
+use crate::seed;
+use crate::service_node::{NodeState, ServiceNode};
+use crate::swarms::Swarm;
+use crate::uptime::Ping;
+use rand::prelude::*;
+use std::collections::BTreeMap;
+
+/// Reported as the swarm of nodes that haven't been assigned one
+const UNASSIGNED_SWARM_ID: u64 = u64::MAX;
 
 #[derive(Serialize, Debug)]
 struct ServiceNodeState {
@@ -11,7 +21,8 @@ struct ServiceNodeState {
     storage_port: u16,
     storage_lmq_port: u16,
     swarm_id: u64,
-    funded: bool
+    funded: bool,
+    active: bool,
 }
 
 #[derive(Serialize, Debug)]
@@ -20,7 +31,10 @@ struct SwarmResult {
     height: u64,
     target_height: u64,
     block_hash: String,
-    hardfork: u8
+    hardfork: u8,
+    /// Only reported to pollers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unchanged: Option<bool>,
 }
 
 #[derive(Serialize, Debug)]
@@ -33,49 +47,243 @@ struct GetNodesRequest {
     params: bool,
 }
 
-fn construct_swarm_json(bc_view: &BlockchainView) -> String {
+/// Fields to report, either as `{"<field>": true, ...}` or as a list of names
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Fields {
+    Flags(BTreeMap<String, bool>),
+    Names(Vec<String>),
+}
+
+impl Fields {
+    fn includes(&self, field: &str) -> bool {
+        match self {
+            Fields::Flags(flags) => flags.get(field) == Some(&true),
+            Fields::Names(names) => names.iter().any(|name| name == field),
+        }
+    }
+}
+
+/// Parameters of `get_n_service_nodes`, as understood by oxend
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct GetNodesParams {
+    /// Leave out decommissioned nodes and those awaiting contributions
+    active_only: bool,
+    /// Report the nodes as of a past block rather than the tip
+    /// (not something oxend can do)
+    height: Option<u64>,
+    /// Every field is reported unless this says otherwise
+    fields: Option<Fields>,
+    /// Only report these nodes, in this order
+    service_node_pubkeys: Vec<String>,
+    /// Report a random sample of this many nodes, unless 0
+    limit: usize,
+    /// Only report `unchanged` if the block hash is still this one
+    poll_block_hash: Option<String>,
+}
+
+impl GetNodesParams {
+    fn poll_block_hash(&self) -> Option<&str> {
+        self.poll_block_hash.as_deref().filter(|hash| !hash.is_empty())
+    }
+
+    fn includes(&self, field: &str) -> bool {
+        // Pollers always get the hash to poll with next time
+        if field == "block_hash" && self.poll_block_hash().is_some() {
+            return true;
+        }
+
+        self.fields.as_ref().is_none_or(|fields| fields.includes(field))
+    }
+}
+
+/// Oxend samples nodes at random. Seeding the sample with the block hash
+/// makes it the same for every request at a given block, and in every run.
+fn sample_rng(block_hash: &str) -> StdRng {
+    let seed = block_hash
+        .get(..16)
+        .and_then(|hash| u64::from_str_radix(hash, 16).ok())
+        .unwrap_or(0);
+
+    seed::derive_rng(seed, seed::RPC_SAMPLE)
+}
+
+/// Leave out the fields that weren't asked for. The list
+/// of nodes itself is always there, even if it is empty.
+fn retain_fields(result: &mut serde_json::Value, params: &GetNodesParams) {
+    let retain = |object: &mut serde_json::Map<String, serde_json::Value>| {
+        let unwanted: Vec<String> = object
+            .keys()
+            .filter(|key| !matches!(key.as_str(), "service_node_states" | "unchanged"))
+            .filter(|key| !params.includes(key))
+            .cloned()
+            .collect();
+
+        for key in unwanted {
+            object.remove(&key);
+        }
+    };
+
+    if let Some(result) = result.as_object_mut() {
+        retain(result);
+    }
+
+    if let Some(states) = result.get_mut("service_node_states").and_then(|v| v.as_array_mut()) {
+        for state in states.iter_mut().filter_map(|state| state.as_object_mut()) {
+            retain(state);
+        }
+    }
+}
+
+fn construct_swarm_json(
+    params: &GetNodesParams,
+    swarms: &[Swarm],
+    awaiting: &[ServiceNode],
+    height: u64,
+    target_height: u64,
+    block_hash: String,
+    hardfork: u8,
+) -> String {
     let mut sn_list = vec![];
 
-    // bc_view needs get_swarms()
-    for swarm in &bc_view.get_swarms() {
-        for sn in &swarm.nodes {
-            let service_node_pubkey = sn.pubkey.clone();
-            let secret_key = sn.seckey.clone();
-            let public_ip = String::from("localhost");
-            let operator_address = String::from("test");
-            let storage_port = sn.port.parse::<u16>().unwrap();
-            // TODO: actually add this port to sn (and check that it is available)
-            let storage_lmq_port = sn.port.parse::<u16>().unwrap() + 200;
-            let swarm_id = swarm.swarm_id;
-            let pubkey_x25519 = sn.pubkey_x25519.clone();
-            let pubkey_ed25519 = sn.ed_keys.pubkey.clone();
-            sn_list.push(ServiceNodeState {
-                service_node_pubkey,
-                pubkey_x25519,
-                pubkey_ed25519,
-                secret_key,
-                public_ip,
-                storage_port,
-                storage_lmq_port,
-                operator_address,
-                swarm_id,
-                funded: true
-            })
+    let assigned = swarms
+        .iter()
+        .flat_map(|swarm| swarm.nodes.iter().map(move |sn| (sn, swarm.swarm_id)));
+    let unassigned = awaiting.iter().map(|sn| (sn, UNASSIGNED_SWARM_ID));
+
+    for (sn, swarm_id) in assigned.chain(unassigned) {
+        let service_node_pubkey = sn.pubkey.clone();
+        let secret_key = sn.seckey.clone();
+        let public_ip = String::from("localhost");
+        let operator_address = String::from("test");
+        let storage_port = sn.port.parse::<u16>().unwrap();
+        // TODO: actually add this port to sn (and check that it is available)
+        let storage_lmq_port = sn.port.parse::<u16>().unwrap() + 200;
+        let pubkey_x25519 = sn.pubkey_x25519.clone();
+        let pubkey_ed25519 = sn.ed_keys.pubkey.clone();
+        sn_list.push(ServiceNodeState {
+            service_node_pubkey,
+            pubkey_x25519,
+            pubkey_ed25519,
+            secret_key,
+            public_ip,
+            storage_port,
+            storage_lmq_port,
+            operator_address,
+            swarm_id,
+            funded: sn.state != NodeState::AwaitingContributions,
+            active: sn.is_active(),
+        })
+    }
+
+    // Requested nodes come in the order they were requested in
+    if !params.service_node_pubkeys.is_empty() {
+        let mut requested = vec![];
+        for pubkey in &params.service_node_pubkeys {
+            if let Some(idx) = sn_list.iter().position(|sn| &sn.service_node_pubkey == pubkey) {
+                requested.push(sn_list.swap_remove(idx));
+            }
         }
+        sn_list = requested;
+    }
+
+    if params.limit > 0 {
+        sn_list.shuffle(&mut sample_rng(&block_hash));
+        sn_list.truncate(params.limit);
     }
+
     let service_node_states = sn_list;
 
     let response = RpcResponse::<SwarmResult> {
         result: SwarmResult {
             service_node_states,
-            height: bc_view.get_height(),
-            target_height: bc_view.get_target_height(),
-            block_hash: bc_view.get_block_hash().clone(),
-            hardfork: bc_view.get_hf()
+            height,
+            target_height,
+            block_hash,
+            hardfork,
+            unchanged: params.poll_block_hash().map(|_| false),
         },
     };
 
-    serde_json::to_string(&response).expect("could not construct json")
+    let mut response = serde_json::to_value(&response).expect("could not construct json");
+    if let Some(result) = response.get_mut("result") {
+        retain_fields(result, params);
+    }
+
+    response.to_string()
+}
+
+/// The nodes as of the tip, or of the block at `params.height`
+fn get_n_service_nodes<V: BlockchainViewable>(bc_view: &V, params: Option<&serde_json::Value>) -> String {
+    let params: GetNodesParams = match params.filter(|params| !params.is_null()) {
+        Some(params) => match serde_json::from_value(params.clone()) {
+            Ok(params) => params,
+            Err(e) => return construct_error_json(&format!("invalid params: {}", e)),
+        },
+        None => GetNodesParams::default(),
+    };
+
+    trace!("get_n_service_nodes params: {:?}", params);
+
+    let tip = bc_view.get_tip();
+    let target_height = tip.height + bc_view.get_sync_lag();
+
+    let block = match params.height {
+        // The swarm layout as of a past block
+        Some(height) => match bc_view.get_block(height) {
+            Some(block) => block,
+            None => return construct_error_json(&format!("unknown height: {}", height)),
+        },
+        None => tip,
+    };
+
+    if params.poll_block_hash() == Some(block.hash.as_str()) {
+        return construct_unchanged_json(&block.hash);
+    }
+
+    // Decommissioned nodes and those awaiting
+    // contributions are not active
+    let (mut swarms, mut awaiting) = (block.swarms, block.awaiting);
+    if params.active_only {
+        for swarm in &mut swarms {
+            swarm.nodes.retain(|sn| sn.is_active());
+        }
+        awaiting.clear();
+    }
+
+    construct_swarm_json(
+        &params,
+        &swarms,
+        &awaiting,
+        block.height,
+        target_height,
+        block.hash,
+        block.hardfork,
+    )
+}
+
+fn construct_unchanged_json(block_hash: &str) -> String {
+    let res = serde_json::json!({
+        "result": {
+            "block_hash": block_hash,
+            "unchanged": true
+        }
+    });
+
+    res.to_string()
+}
+
+
+fn construct_error_json(message: &str) -> String {
+    let res = serde_json::json!({
+        "error": {
+            "code": -1,
+            "message": message
+        }
+    });
+
+    res.to_string()
 }
 
 fn construct_bc_test_json() -> String {
@@ -111,7 +319,8 @@ fn construct_report_json() -> String {
     res.to_string()
 }
 
-fn process_json_rpc(bc_view: &BlockchainView, req_body: serde_json::Value) -> String {
+/// `port` is the port of this daemon
+fn process_json_rpc<V: BlockchainViewable>(bc_view: &V, port: u16, req_body: serde_json::Value) -> String {
     let mut res = String::new();
 
     if let Some(Some(method)) = req_body.get("method").map(|v| v.as_str()) {
@@ -119,31 +328,14 @@ fn process_json_rpc(bc_view: &BlockchainView, req_body: serde_json::Value) -> St
 
         match method {
             "get_n_service_nodes" => {
-
-                let mut real_messenger = false;
-
-                if let Some(params) = req_body.get("params") {
-
-                    if let Some(Some(active_only)) = params.get("active_only").map(|v| v.as_bool()) {
-                        if active_only {
-                        
-                            real_messenger = true;
-                            println!("GET_N_SERVICE_NODES params, {:?}", params);
-                        }
-                    }
-                }
-
-                res = construct_swarm_json(&bc_view);
-
-                if real_messenger {
-                    dbg!(&res);
-                }
-
+                res = get_n_service_nodes(bc_view, req_body.get("params"));
             }
             "perform_blockchain_test" => {
                 res = construct_bc_test_json();
             }
             "storage_server_ping" => {
+                let params = req_body.get("params").cloned().unwrap_or_default();
+                bc_view.record_ping(Ping::from_params(&params, port));
                 res = construct_ping_json();
             }
             "report_peer_storage_server_status" => {
@@ -160,54 +352,131 @@ fn process_json_rpc(bc_view: &BlockchainView, req_body: serde_json::Value) -> St
 }
 
 use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-pub fn start_http_server2(bc_view: BlockchainView, port: u16) -> std::thread::JoinHandle<()> {
+/// How often a running server checks whether it has been stopped
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(5);
 
-    let thread = std::thread::spawn(move || {
+/// A running RPC server, shut down with `stop` or when dropped
+pub struct RpcServer {
+    port: u16,
+    stopped: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl RpcServer {
+    /// Stop serving requests and release the port. Requests that
+    /// are being handled are still answered in the background.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return,
+        };
+
+        self.stopped.store(true, Ordering::SeqCst);
+        let _ = thread.join();
+
+        // The listening socket is closed by a thread of its own,
+        // so only consider the port released once it can be bound
+        for _ in 0..200 {
+            if std::net::TcpListener::bind(("0.0.0.0", self.port)).is_ok() {
+                info!("stopped RPC server on port {}", self.port);
+                return;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
 
-        let addr = format!("0.0.0.0:{}", port);
+        warn!("port {} is still in use after stopping its RPC server", self.port);
+    }
+}
 
-        info!("Running RPC Server on {}", addr);
-        
-        rouille::start_server(addr, move |request| {
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
 
-            let mut data = request.data().expect("data already retrieved?");
+/// Serve `bc_view` on `port` until stopped. Fails if the port is taken.
+pub fn start_http_server2<V>(bc_view: V, port: u16) -> Result<RpcServer, String>
+where
+    V: BlockchainViewable + Send + Sync + 'static,
+{
 
-            let mut buf = Vec::new();
-            match data.read_to_end(&mut buf) {
-                Ok(_) => (),
-                Err(_) => return rouille::Response::text("Failed to read body")
-            };
+    let addr = format!("0.0.0.0:{}", port);
 
-            let req_body = String::from_utf8_lossy(&buf);
+    let server = rouille::Server::new(&addr, move |request| {
 
-            let mut res_body = String::new();
+        let mut data = request.data().expect("data already retrieved?");
 
-            if request.url() == "/json_rpc" {
-                if let Ok(val) = serde_json::from_str::<serde_json::Value>(&req_body) {
-                    res_body = process_json_rpc(&bc_view, val);
-                } else {
-                    warn!("invalid json: \n{:?}", &req_body);
-                    println!("invalid json: \n{:?}", &req_body);
-                }
-            } else if request.url() == "/lsrpc" {
-                println!("got an lsrpc request");
-                res_body = "OK".to_owned();
+        let mut buf = Vec::new();
+        match data.read_to_end(&mut buf) {
+            Ok(_) => (),
+            Err(_) => return rouille::Response::text("Failed to read body")
+        };
+
+        let req_body = String::from_utf8_lossy(&buf);
+
+        let fault = bc_view.rpc_fault();
+
+        match fault {
+            // As close to a refused connection as it gets
+            Some(RpcFault::Unreachable) => {
+                return rouille::Response::text("").with_status_code(503)
             }
+            Some(RpcFault::Delay(delay)) => std::thread::sleep(delay),
+            _ => {}
+        }
 
-            rouille::Response::from_data("application/json", res_body.as_bytes())
-        });
+        let mut res_body = String::new();
 
+        if request.url() == "/json_rpc" {
+            if fault == Some(RpcFault::Error) {
+                res_body = construct_error_json("internal error");
+            } else if let Ok(val) = serde_json::from_str::<serde_json::Value>(&req_body) {
+                res_body = process_json_rpc(&bc_view, port, val);
+            } else {
+                warn!("invalid json: \n{:?}", &req_body);
+                println!("invalid json: \n{:?}", &req_body);
+            }
+        } else if request.url() == "/lsrpc" {
+            println!("got an lsrpc request");
+            res_body = "OK".to_owned();
+        }
 
-    });
+        rouille::Response::from_data("application/json", res_body.as_bytes())
+    })
+    .map_err(|e| format!("could not start RPC server on {}: {}", addr, e))?;
 
-    thread
+    info!("Running RPC Server on {}", addr);
 
+    let stopped = Arc::new(AtomicBool::new(false));
+    let stopped_clone = stopped.clone();
+
+    let thread = std::thread::spawn(move || {
+        while !stopped_clone.load(Ordering::SeqCst) {
+            server.poll();
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    });
+
+    Ok(RpcServer {
+        port,
+        stopped,
+        thread: Some(thread),
+    })
 }
 
 #[allow(dead_code)]
 /// Starts a new thread
-pub fn start_http_server(bc_view: BlockchainView, port : u16) -> std::thread::JoinHandle<()> {
+pub fn start_http_server<V>(bc_view: V, port : u16) -> std::thread::JoinHandle<()>
+where
+    V: BlockchainViewable + Send + Sync + 'static,
+{
 
     let thread = std::thread::spawn(move || {
         let server = simple_server::Server::new(move |req, mut res| {
@@ -217,7 +486,7 @@ pub fn start_http_server(bc_view: BlockchainView, port : u16) -> std::thread::Jo
 
             if req.uri() == "/json_rpc" {
                 if let Ok(val) = serde_json::from_str::<serde_json::Value>(&req_body) {
-                    res_body = process_json_rpc(&bc_view, val);
+                    res_body = process_json_rpc(&bc_view, port, val);
                 } else {
                     warn!("invalid json: \n{:?}", &req_body);
                     println!("invalid json: \n{:?}", &req_body);
@@ -240,3 +509,293 @@ pub fn start_http_server(bc_view: BlockchainView, port : u16) -> std::thread::Jo
 
     thread
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{BlockRecord, Ed25519KeyPair, KeyPair, X25519KeyPair};
+    use crate::daemon::StaticView;
+    use serde_json::json;
+    use std::path::Path;
+
+    const DAEMON_PORT: u16 = 22129;
+
+    /// Fake, but stable, keys derived from the port
+    fn node(port: u16, state: NodeState) -> ServiceNode {
+        let key = |kind: u8| format!("{:02x}{:062x}", kind, port);
+
+        let mut sn = ServiceNode::new(
+            port.to_string(),
+            KeyPair { pubkey: key(1), seckey: key(2) },
+            Ed25519KeyPair { pubkey: key(3), seckey: key(4) },
+            X25519KeyPair { pubkey: key(5), seckey: key(6) },
+            DAEMON_PORT,
+        );
+        sn.state = state;
+        sn
+    }
+
+    fn block(height: u64, hardfork: u8, swarms: Vec<Swarm>, awaiting: Vec<ServiceNode>) -> BlockRecord {
+        BlockRecord {
+            height,
+            hash: format!("{:064x}", height),
+            hardfork,
+            swarms,
+            awaiting,
+            events: vec![],
+        }
+    }
+
+    /// Two blocks: a single swarm, then a second swarm, a decommissioned
+    /// node and a hard fork. A node awaits contributions throughout.
+    fn view() -> StaticView {
+        let awaiting = vec![node(5905, NodeState::AwaitingContributions)];
+
+        let first = vec![Swarm {
+            swarm_id: 0,
+            nodes: vec![node(5901, NodeState::Active), node(5902, NodeState::Active)],
+        }];
+
+        let second = vec![
+            Swarm {
+                swarm_id: 0,
+                nodes: vec![
+                    node(5901, NodeState::Active),
+                    node(5902, NodeState::Active),
+                    node(5903, NodeState::Decommissioned { credit_blocks: 40 }),
+                ],
+            },
+            Swarm {
+                swarm_id: 1 << 62,
+                nodes: vec![node(5904, NodeState::Active)],
+            },
+        ];
+
+        StaticView {
+            blocks: vec![
+                block(20, 15, first, awaiting.clone()),
+                block(21, 16, second, awaiting),
+            ],
+            ..StaticView::default()
+        }
+    }
+
+    fn request(view: &StaticView, req: serde_json::Value) -> String {
+        process_json_rpc(view, DAEMON_PORT, req)
+    }
+
+    /// Compare `response` with `testdata/rpc/<name>.json`. Set
+    /// `UPDATE_GOLDEN` to (re)write the file from the response instead.
+    fn check_golden(name: &str, response: &str) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join("rpc");
+        let path = dir.join(format!("{}.json", name));
+
+        let actual: serde_json::Value = serde_json::from_str(response)
+            .unwrap_or_else(|e| panic!("response is not json ({}): {:?}", e, response));
+        let pretty = serde_json::to_string_pretty(&actual).unwrap() + "\n";
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(&dir).expect("could not create golden file directory");
+            std::fs::write(&path, pretty).expect("could not write golden file");
+            return;
+        }
+
+        let golden = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+            panic!("missing {}, run with UPDATE_GOLDEN=1 to create it", path.display())
+        });
+        let expected: serde_json::Value =
+            serde_json::from_str(&golden).expect("golden file is not json");
+
+        assert_eq!(actual, expected, "response differs from {}:\n{}", path.display(), pretty);
+    }
+
+    #[test]
+    fn get_n_service_nodes() {
+        let res = request(&view(), json!({ "method": "get_n_service_nodes" }));
+        check_golden("get_n_service_nodes", &res);
+    }
+
+    #[test]
+    fn get_n_service_nodes_active_only() {
+        let res = request(
+            &view(),
+            json!({ "method": "get_n_service_nodes", "params": { "active_only": true } }),
+        );
+        check_golden("get_n_service_nodes_active_only", &res);
+    }
+
+    #[test]
+    fn get_n_service_nodes_at_height() {
+        let res = request(
+            &view(),
+            json!({ "method": "get_n_service_nodes", "params": { "height": 20 } }),
+        );
+        check_golden("get_n_service_nodes_at_height", &res);
+    }
+
+    #[test]
+    fn get_n_service_nodes_unknown_height() {
+        let res = request(
+            &view(),
+            json!({ "method": "get_n_service_nodes", "params": { "height": 22 } }),
+        );
+        check_golden("get_n_service_nodes_unknown_height", &res);
+    }
+
+    #[test]
+    fn get_n_service_nodes_syncing() {
+        let view = StaticView { sync_lag: 3, ..view() };
+        let res = request(&view, json!({ "method": "get_n_service_nodes" }));
+        check_golden("get_n_service_nodes_syncing", &res);
+    }
+
+    #[test]
+    fn get_n_service_nodes_no_blocks() {
+        let res = request(&StaticView::default(), json!({ "method": "get_n_service_nodes" }));
+        check_golden("get_n_service_nodes_no_blocks", &res);
+    }
+
+    /// What the storage server asks for
+    #[test]
+    fn get_n_service_nodes_fields() {
+        let res = request(
+            &view(),
+            json!({
+                "method": "get_n_service_nodes",
+                "params": {
+                    "active_only": true,
+                    "fields": {
+                        "service_node_pubkey": true,
+                        "swarm_id": true,
+                        "storage_port": true,
+                        "public_ip": true,
+                        "height": true,
+                        "block_hash": true,
+                        "hardfork": true,
+                        "funded": false,
+                    }
+                }
+            }),
+        );
+        check_golden("get_n_service_nodes_fields", &res);
+    }
+
+    #[test]
+    fn get_n_service_nodes_field_list() {
+        let res = request(
+            &view(),
+            json!({
+                "method": "get_n_service_nodes",
+                "params": { "fields": ["pubkey_ed25519", "active", "target_height"] }
+            }),
+        );
+        check_golden("get_n_service_nodes_field_list", &res);
+    }
+
+    #[test]
+    fn get_n_service_nodes_pubkeys() {
+        let pubkey = |port| node(port, NodeState::Active).pubkey;
+        let res = request(
+            &view(),
+            json!({
+                "method": "get_n_service_nodes",
+                "params": {
+                    "service_node_pubkeys": [pubkey(5904), pubkey(5999), pubkey(5901)],
+                    "fields": { "service_node_pubkey": true, "storage_port": true }
+                }
+            }),
+        );
+        check_golden("get_n_service_nodes_pubkeys", &res);
+    }
+
+    #[test]
+    fn get_n_service_nodes_limit() {
+        let req = json!({
+            "method": "get_n_service_nodes",
+            "params": { "limit": 2, "fields": { "storage_port": true } }
+        });
+        let res = request(&view(), req.clone());
+        check_golden("get_n_service_nodes_limit", &res);
+
+        // The sample only changes with the block
+        assert_eq!(res, request(&view(), req));
+    }
+
+    #[test]
+    fn get_n_service_nodes_poll_unchanged() {
+        let view = view();
+        let res = request(
+            &view,
+            json!({
+                "method": "get_n_service_nodes",
+                "params": { "poll_block_hash": view.get_tip().hash }
+            }),
+        );
+        check_golden("get_n_service_nodes_poll_unchanged", &res);
+    }
+
+    #[test]
+    fn get_n_service_nodes_poll_changed() {
+        let res = request(
+            &view(),
+            json!({
+                "method": "get_n_service_nodes",
+                "params": {
+                    "poll_block_hash": format!("{:064x}", 20),
+                    "fields": { "storage_port": true, "height": true }
+                }
+            }),
+        );
+        check_golden("get_n_service_nodes_poll_changed", &res);
+    }
+
+    #[test]
+    fn get_n_service_nodes_invalid_params() {
+        let res = request(
+            &view(),
+            json!({ "method": "get_n_service_nodes", "params": { "limit": "all" } }),
+        );
+        check_golden("get_n_service_nodes_invalid_params", &res);
+    }
+
+    #[test]
+    fn perform_blockchain_test() {
+        let res = request(&view(), json!({ "method": "perform_blockchain_test" }));
+        check_golden("perform_blockchain_test", &res);
+    }
+
+    #[test]
+    fn storage_server_ping() {
+        let view = view();
+        let res = request(
+            &view,
+            json!({
+                "method": "storage_server_ping",
+                "params": {
+                    "version": [2, 0, 7],
+                    "https_port": 5901,
+                    "pubkey_ed25519": node(5901, NodeState::Active).ed_keys.pubkey,
+                }
+            }),
+        );
+        check_golden("storage_server_ping", &res);
+
+        let pings = view.pings.lock().unwrap();
+        assert_eq!(pings.len(), 1);
+        assert_eq!(pings[0].port.as_deref(), Some("5901"));
+        assert_eq!(pings[0].version.as_deref(), Some("2.0.7"));
+        assert_eq!(pings[0].lokid_port, DAEMON_PORT);
+    }
+
+    #[test]
+    fn report_peer_storage_server_status() {
+        let res = request(&view(), json!({ "method": "report_peer_storage_server_status" }));
+        check_golden("report_peer_storage_server_status", &res);
+    }
+
+    #[test]
+    fn unknown_method() {
+        let res = request(&view(), json!({ "method": "get_info" }));
+        assert_eq!(res, "");
+    }
+}
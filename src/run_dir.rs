@@ -0,0 +1,106 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything produced by a single invocation of the harness:
+/// `runs/<timestamp>-<scenario>-<seed>[-<n>]/{playground,log,report.json}`
+pub struct RunDir {
+    root: PathBuf,
+}
+
+/// Past runs, oldest first (the timestamp prefix sorts chronologically)
+fn list_runs(base: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut runs = vec![];
+
+    for entry in std::fs::read_dir(base)? {
+        let entry = entry?;
+        // Doesn't follow symlinks, so `latest` is skipped
+        if entry.file_type()?.is_dir() {
+            runs.push(entry.path());
+        }
+    }
+
+    runs.sort();
+
+    Ok(runs)
+}
+
+fn remove_old_runs(base: &Path, keep: usize) -> io::Result<()> {
+    let runs = list_runs(base)?;
+
+    if runs.len() > keep {
+        for run in &runs[..runs.len() - keep] {
+            println!("Removing old run {}", run.display());
+            std::fs::remove_dir_all(run)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn update_latest(base: &Path, name: &str) -> io::Result<()> {
+    let latest = base.join("latest");
+
+    if latest.symlink_metadata().is_ok() {
+        std::fs::remove_file(&latest)?;
+    }
+
+    // Relative, so that `runs` can be moved around
+    std::os::unix::fs::symlink(name, &latest)
+}
+
+#[cfg(not(unix))]
+fn update_latest(_base: &Path, _name: &str) -> io::Result<()> {
+    Ok(())
+}
+
+impl RunDir {
+    /// Create a new run directory in `base` and point `base/latest` at it.
+    /// The oldest runs are removed so that at most `keep` of them
+    /// (including this one) remain; 0 keeps all of them.
+    pub fn create(base: &Path, label: &str, seed: u64, keep: usize) -> io::Result<RunDir> {
+        std::fs::create_dir_all(base)?;
+
+        if keep > 0 {
+            remove_old_runs(base, keep - 1)?;
+        }
+
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let prefix = format!("{}-{}-{}", timestamp, label, seed);
+
+        // Runs started within the same second get a suffix
+        let mut name = prefix.clone();
+        for attempt in 2.. {
+            match std::fs::create_dir(base.join(&name)) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    name = format!("{}-{}", prefix, attempt);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let root = base.join(&name);
+
+        update_latest(base, &name)?;
+
+        Ok(RunDir { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Working directories of the storage servers
+    pub fn playground(&self) -> PathBuf {
+        self.root.join("playground")
+    }
+
+    pub fn log_dir(&self) -> PathBuf {
+        self.root.join("log")
+    }
+
+    pub fn report_path(&self) -> PathBuf {
+        self.root.join("report.json")
+    }
+}
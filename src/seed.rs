@@ -0,0 +1,23 @@
+use rand::prelude::*;
+
+// Every source of randomness in the harness gets its own
+// stream, derived from a single seed given on the command line
+pub const BLOCKCHAIN: u64 = 0;
+pub const SWARM_MANAGER: u64 = 1;
+pub const TEST_CONTEXT: u64 = 2;
+pub const SCENARIO: u64 = 3;
+pub const BLOCK_PRODUCER: u64 = 4;
+pub const RPC_SAMPLE: u64 = 5;
+
+/// Mix `stream` into `seed` (splitmix64), so that
+/// neighbouring streams produce unrelated sequences
+pub fn derive_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub fn derive_rng(seed: u64, stream: u64) -> StdRng {
+    StdRng::seed_from_u64(derive_seed(seed, stream))
+}
@@ -1,7 +1,21 @@
 
 use crate::blockchain::{KeyPair, X25519KeyPair, Ed25519KeyPair};
+use std::hash::{Hash, Hasher};
 
-#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+/// Registration state as reported by oxend. Deregistered nodes
+/// are simply removed from the swarms, just like in oxend.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NodeState {
+    /// Registered, but not fully staked yet, so not assigned to a swarm
+    AwaitingContributions,
+    #[default]
+    Active,
+    /// Temporarily out of its swarm. The node is deregistered once
+    /// it has been decommissioned for `credit_blocks` blocks.
+    Decommissioned { credit_blocks: u64 },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ServiceNode {
     pub port: String,
     pub pubkey: String,
@@ -10,6 +24,25 @@ pub struct ServiceNode {
     pub seckey_x25519: String,
     pub ed_keys: Ed25519KeyPair,
     pub lokid_port: u16,
+    #[serde(default)]
+    pub state: NodeState,
+}
+
+// A node stays the same node whatever its state,
+// e.g. when looking up its server process
+impl PartialEq for ServiceNode {
+    fn eq(&self, other: &ServiceNode) -> bool {
+        self.pubkey == other.pubkey && self.port == other.port
+    }
+}
+
+impl Eq for ServiceNode {}
+
+impl Hash for ServiceNode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pubkey.hash(state);
+        self.port.hash(state);
+    }
 }
 
 impl ServiceNode {
@@ -22,6 +55,11 @@ impl ServiceNode {
             seckey_x25519: x_keys.seckey,
             ed_keys,
             lokid_port,
+            state: NodeState::Active,
         }
     }
+
+    pub fn is_active(&self) -> bool {
+        self.state == NodeState::Active
+    }
 }
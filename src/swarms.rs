@@ -2,11 +2,15 @@ use rand::prelude::*;
 use rand::seq::SliceRandom;
 use crate::blockchain::{KeyPair, X25519KeyPair, Ed25519KeyPair};
 use std::fmt::{self, Debug};
-use crate::service_node::ServiceNode;
+use std::path::{Path, PathBuf};
+use crate::event_log::{EventLog, NetworkEvent};
+use crate::seed;
+use crate::service_node::{NodeState, ServiceNode};
+use crate::timeline::{Action, TimelineRecorder};
 
 use byteorder::{BigEndian, WriteBytesExt};
 
-#[derive(Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Swarm {
     pub swarm_id: u64,
     pub nodes: Vec<ServiceNode>,
@@ -17,6 +21,9 @@ impl Debug for Swarm {
         write!(f, "{}: ", &self.swarm_id).expect("printing a swarm");
         for sn in &self.nodes {
             write!(f, "{:?} ", sn.port).expect("printing a swarm");
+            if !sn.is_active() {
+                write!(f, "({:?}) ", sn.state).expect("printing a swarm");
+            }
         }
         Ok(())
     }
@@ -28,10 +35,15 @@ pub struct Stats {
 
 pub struct SwarmManager {
     pub swarms: Vec<Swarm>,
+    /// Registered nodes that are not fully staked yet
+    pub awaiting: Vec<ServiceNode>,
     sn_to_child: std::collections::HashMap<ServiceNode, std::process::Child>,
     pub stats: Stats,
     rng: StdRng,
     exe_path: String,
+    playground: PathBuf,
+    pub event_log: EventLog,
+    pub recorder: TimelineRecorder,
 }
 
 // pub type PubKey = [u64; 4];
@@ -46,6 +58,56 @@ impl Debug for PubKey {
     }
 }
 
+/// get index into `swarms` by client's public key
+pub fn swarm_idx_for_pk(swarms: &[Swarm], pk: &PubKey) -> usize {
+    let pk = pk.data;
+    let res = pk[0] ^ pk[1] ^ pk[2] ^ pk[3];
+
+    const MAX_VALUE: u64 = std::u64::MAX - 1;
+
+    let (mut cur_best, mut min_dist) = (0, std::u64::MAX);
+    let mut leftmost = std::u64::MAX;
+    let mut leftmost_idx = 0;
+    let mut rightmost = 0;
+    let mut rightmost_idx = 0;
+
+    for (idx, sw) in swarms.iter().enumerate() {
+        let dist = if sw.swarm_id > res {
+            sw.swarm_id - res
+        } else {
+            res - sw.swarm_id
+        };
+        if dist < min_dist {
+            min_dist = dist;
+            cur_best = idx;
+        }
+
+        if sw.swarm_id < leftmost {
+            leftmost = sw.swarm_id;
+            leftmost_idx = idx;
+        }
+
+        if sw.swarm_id > rightmost {
+            rightmost = sw.swarm_id;
+            rightmost_idx = idx;
+        }
+    }
+
+    if res > rightmost {
+        let dist = (MAX_VALUE - res) + leftmost;
+        if dist < min_dist {
+            cur_best = leftmost_idx;
+        }
+    } else if res < leftmost {
+        let dist = res + (MAX_VALUE - rightmost);
+        if dist < min_dist {
+            cur_best = rightmost_idx;
+        }
+    }
+
+    cur_best
+}
+
 const MIN_SWARM_SIZE: usize = 3;
 
 impl PubKey {
@@ -83,15 +145,18 @@ impl PubKey {
     }
 }
 
-pub fn spawn_service_node(sn: &ServiceNode, exe_path: &str) -> Option<std::process::Child> {
+pub fn spawn_service_node(
+    sn: &ServiceNode,
+    exe_path: &str,
+    playground: &Path,
+) -> Option<std::process::Child> {
     let mut server_process = std::process::Command::new(exe_path);
 
-    let path = std::path::Path::new("playground");
-    if !path.exists() {
-        std::fs::create_dir(&path).unwrap();
+    if !playground.exists() {
+        std::fs::create_dir_all(playground).unwrap();
     }
 
-    let path = path.join(&sn.port);
+    let path = playground.join(&sn.port);
 
     let path = std::path::Path::new(&path);
 
@@ -158,24 +223,26 @@ pub fn spawn_service_node(sn: &ServiceNode, exe_path: &str) -> Option<std::proce
 pub enum SpawnStrategy {
     Now,
     Later,
+    /// The server has been running since before the registration
+    AlreadyRunning,
 }
 
 impl SwarmManager {
-    pub fn new(exe_path: &str) -> SwarmManager {
+    pub fn new(exe_path: &str, playground: &Path, seed: u64) -> SwarmManager {
         SwarmManager {
             swarms: vec![],
+            awaiting: vec![],
             sn_to_child: std::collections::HashMap::new(),
             stats: Stats { dissolved: 0 },
-            rng: StdRng::seed_from_u64(1),
+            rng: seed::derive_rng(seed, seed::SWARM_MANAGER),
             exe_path: exe_path.to_owned(),
+            playground: playground.to_owned(),
+            event_log: EventLog::new(&playground.join("events.jsonl")),
+            recorder: TimelineRecorder::new(&playground.join("timeline.jsonl")),
         }
     }
 
     pub fn add_swarm<'a>(&mut self, nodes: Vec<(u16, KeyPair, Ed25519KeyPair, X25519KeyPair)>, lokid_ports: &[u16]) {
-        let swarm_id = self.get_next_swarm_id();
-
-        info!("using {} as swarm id", swarm_id);
-
         let nodes: Vec<ServiceNode> = nodes
             .into_iter()
             .map(|(port, keypair, ed_keys, x_keys)| {
@@ -185,16 +252,36 @@ impl SwarmManager {
             })
             .collect();
 
+        self.add_swarm_nodes(nodes);
+    }
+
+    /// Create a new swarm out of `nodes`, starting their servers
+    pub fn add_swarm_nodes(&mut self, nodes: Vec<ServiceNode>) {
+        let swarm_id = self.get_next_swarm_id();
+
+        info!("using {} as swarm id", swarm_id);
+
         for node in &nodes {
-            if let Some(child) = spawn_service_node(&node, &self.exe_path) {
+            if let Some(child) = spawn_service_node(&node, &self.exe_path, &self.playground) {
                 info!("NEW SNODE: {}, pid: {}", &node.port, child.id());
                 println!("NEW SNODE: {}, pid: {}", &node.port, child.id());
                 self.sn_to_child.insert(node.clone(), child);
+                self.recorder.record(Action::Started { node: node.clone() });
             } else {
                 error!("Could not spawn node!");
             }
         }
 
+        self.event_log.record(NetworkEvent::SwarmCreated { swarm_id });
+
+        for node in &nodes {
+            self.event_log.record(NetworkEvent::Registered {
+                port: node.port.clone(),
+                pubkey: node.pubkey.clone(),
+                swarm_id,
+            });
+        }
+
         let swarm = Swarm { swarm_id, nodes };
 
         self.swarms.push(swarm);
@@ -214,58 +301,24 @@ impl SwarmManager {
 
         for node in swarm.nodes {
             let target = self.swarms.choose_mut(&mut self.rng).unwrap();
+
+            self.event_log.record(NetworkEvent::MovedBetweenSwarms {
+                port: node.port.clone(),
+                from: swarm.swarm_id,
+                to: target.swarm_id,
+            });
+
             target.nodes.push(node);
         }
+
+        self.event_log.record(NetworkEvent::SwarmDissolved {
+            swarm_id: swarm.swarm_id,
+        });
     }
 
     /// get index into swarms by client's public key
     pub fn get_swarm_by_pk(&self, pk: &PubKey) -> usize {
-        let pk = pk.data;
-        let res = pk[0] ^ pk[1] ^ pk[2] ^ pk[3];
-
-        const MAX_VALUE: u64 = std::u64::MAX - 1;
-
-        let (mut cur_best, mut min_dist) = (0, std::u64::MAX);
-        let mut leftmost = std::u64::MAX;
-        let mut leftmost_idx = 0;
-        let mut rightmost = 0;
-        let mut rightmost_idx = 0;
-
-        for (idx, sw) in self.swarms.iter().enumerate() {
-            let dist = if sw.swarm_id > res {
-                sw.swarm_id - res
-            } else {
-                res - sw.swarm_id
-            };
-            if dist < min_dist {
-                min_dist = dist;
-                cur_best = idx;
-            }
-
-            if sw.swarm_id < leftmost {
-                leftmost = sw.swarm_id;
-                leftmost_idx = idx;
-            }
-
-            if sw.swarm_id > rightmost {
-                rightmost = sw.swarm_id;
-                rightmost_idx = idx;
-            }
-        }
-
-        if res > rightmost {
-            let dist = (MAX_VALUE - res) + leftmost;
-            if dist < min_dist {
-                cur_best = leftmost_idx;
-            }
-        } else if res < leftmost {
-            let dist = res + (MAX_VALUE - rightmost);
-            if dist < min_dist {
-                cur_best = rightmost_idx;
-            }
-        }
-
-        cur_best
+        swarm_idx_for_pk(&self.swarms, pk)
     }
 
     // This seems to work fine, but I could add some unit tests
@@ -299,8 +352,28 @@ impl SwarmManager {
     pub fn disconnect_snode(&mut self) -> ServiceNode {
         let swarm = &self.swarms.choose(&mut self.rng).unwrap();
 
-        let snode = swarm.nodes.choose(&mut self.rng).unwrap();
+        let snode = swarm.nodes.choose(&mut self.rng).unwrap().clone();
+
+        self.disconnect(&snode);
+
+        snode
+    }
+
+    /// Same as `disconnect_snode`, but for the snode listening on `port`.
+    /// Only running snodes can be disconnected.
+    pub fn disconnect_snode_by_port(&mut self, port: &str) -> Option<ServiceNode> {
+        let snode = self
+            .sn_to_child
+            .keys()
+            .find(|sn| sn.port == port)?
+            .clone();
+
+        self.disconnect(&snode);
 
+        Some(snode)
+    }
+
+    fn disconnect(&mut self, snode: &ServiceNode) {
         match crate::send_req_to_quit(snode) {
             Ok(()) => {
                 self.sn_to_child
@@ -315,7 +388,12 @@ impl SwarmManager {
         info!("disconnected snode: {}", snode.port);
         println!("disconnected snode: {}", snode.port);
 
-        snode.clone()
+        self.event_log.record(NetworkEvent::Disconnected {
+            port: snode.port.clone(),
+        });
+        self.recorder.record(Action::Disconnected {
+            port: snode.port.clone(),
+        });
     }
 
     fn handle_dropped(&mut self, swarm_idx: usize, node: ServiceNode) {
@@ -367,6 +445,11 @@ impl SwarmManager {
                 &big_swarm.swarm_id,
                 &swarm.swarm_id
             );
+            self.event_log.record(NetworkEvent::MovedBetweenSwarms {
+                port: mov_node.port.clone(),
+                from: big_swarm.swarm_id,
+                to: swarm.swarm_id,
+            });
             swarm.nodes.push(mov_node);
         } else {
             // dissolve the swarm
@@ -378,30 +461,70 @@ impl SwarmManager {
     /// TODO: only drop nodes from swarms that have at least 3 nodes
     pub fn drop_snode(&mut self) {
         let swarm_idx = self.rng.gen_range(0, self.swarms.len());
-        let swarm = &mut self.swarms[swarm_idx];
+        let node_idx = self.rng.gen_range(0, self.swarms[swarm_idx].nodes.len());
 
-        let node_idx = self.rng.gen_range(0, swarm.nodes.len());
-        let node = swarm.nodes.remove(node_idx);
+        self.drop_snode_at(swarm_idx, node_idx);
+    }
 
-        let _ = crate::send_req_to_quit(&node);
-        self.sn_to_child
-            .remove(&node)
-            .expect("child entry did not exist");
+    fn drop_snode_at(&mut self, swarm_idx: usize, node_idx: usize) {
+        let swarm = &mut self.swarms[swarm_idx];
+        let node = swarm.nodes.remove(node_idx);
 
         info!(
             "dropping snode {} from swarm {}",
             &node.port, &swarm.swarm_id
         );
 
+        self.event_log.record(NetworkEvent::Deregistered {
+            port: node.port.clone(),
+            pubkey: node.pubkey.clone(),
+            swarm_id: swarm.swarm_id,
+        });
+
+        self.stop(&node);
+
         self.handle_dropped(swarm_idx, node);
     }
 
+    fn stop(&mut self, sn: &ServiceNode) {
+        // The node might have been disconnected before being deregistered
+        if self.sn_to_child.remove(sn).is_none() {
+            warn!("snode {} is not running", sn.port);
+            return;
+        }
+
+        let _ = crate::send_req_to_quit(sn);
+
+        self.recorder.record(Action::Stopped {
+            port: sn.port.clone(),
+        });
+    }
+
+    /// Shut down the server of the snode on `port` for good, e.g. once
+    /// it has been deregistered. The swarms are left as they are.
+    pub fn stop_snode_by_port(&mut self, port: &str) -> Option<ServiceNode> {
+        let snode = self
+            .sn_to_child
+            .keys()
+            .find(|sn| sn.port == port)?
+            .clone();
+
+        self.stop(&snode);
+
+        Some(snode)
+    }
+
     pub fn restore_snode(&mut self, sn: &ServiceNode) {
         // TODO: check that snode actually exists
         info!("Restore SNODE: {}", &sn.port);
-        let child = spawn_service_node(&sn, &self.exe_path).expect("error spawning a service node");
+        let child = spawn_service_node(&sn, &self.exe_path, &self.playground).expect("error spawning a service node");
         self.sn_to_child.insert(sn.clone(), child);
 
+        self.event_log.record(NetworkEvent::Restored {
+            port: sn.port.clone(),
+        });
+        self.recorder.record(Action::Restored { node: sn.clone() });
+
         // Note: we don't apply any swarm changes since
         // we haven't properly deregistered in the first place
     }
@@ -413,13 +536,11 @@ impl SwarmManager {
 
         // TODO: spawn a node but register it later on the first ping
         match spawn {
-            SpawnStrategy::Now => {
-                let child = spawn_service_node(&sn, &self.exe_path).expect("error spawning a service node");
-                self.sn_to_child.insert(sn.clone(), child);
-            }
+            SpawnStrategy::Now => self.start_snode(sn),
             SpawnStrategy::Later => {
                 info!(" - it will be registered now, but instantiated later");
             }
+            SpawnStrategy::AlreadyRunning => {}
         }
 
         // Figure out which swarm this node is to join
@@ -429,6 +550,12 @@ impl SwarmManager {
 
         rand_swarm.nodes.push(sn.clone());
 
+        self.event_log.record(NetworkEvent::Registered {
+            port: sn.port.clone(),
+            pubkey: sn.pubkey.clone(),
+            swarm_id: rand_swarm.swarm_id,
+        });
+
         // See if we need to make a new swarm
         let total_extra = self.swarms.iter().fold(0, |sum, x| {
             if x.nodes.len() > MIN_SWARM_SIZE {
@@ -453,10 +580,25 @@ impl SwarmManager {
                 let idx = self.rng.gen_range(0, rand_swarm.nodes.len());
 
                 let node = rand_swarm.nodes.remove(idx);
-                nodes_to_move.push(node);
+                nodes_to_move.push((node, rand_swarm.swarm_id));
             }
 
             let swarm_id = self.get_next_swarm_id();
+
+            self.event_log.record(NetworkEvent::SwarmCreated { swarm_id });
+
+            let nodes_to_move = nodes_to_move
+                .into_iter()
+                .map(|(node, from)| {
+                    self.event_log.record(NetworkEvent::MovedBetweenSwarms {
+                        port: node.port.clone(),
+                        from,
+                        to: swarm_id,
+                    });
+                    node
+                })
+                .collect();
+
             let swarm = Swarm {
                 swarm_id,
                 nodes: nodes_to_move,
@@ -466,10 +608,175 @@ impl SwarmManager {
         }
     }
 
+    /// Register a node that still awaits contributions: its server
+    /// is started, but it doesn't join a swarm until it is funded
+    pub fn register_unfunded(&mut self, sn: &ServiceNode) {
+        info!("NEW UNFUNDED SNODE: {}", &sn.port);
+
+        let mut sn = sn.clone();
+        sn.state = NodeState::AwaitingContributions;
+
+        self.start_snode(&sn);
+
+        self.event_log.record(NetworkEvent::AwaitingContributions {
+            port: sn.port.clone(),
+            pubkey: sn.pubkey.clone(),
+        });
+
+        self.awaiting.push(sn);
+    }
+
+    /// The node on `port` got its remaining contributions and joins a swarm
+    pub fn fund_snode(&mut self, port: &str) -> Result<(), ()> {
+        let idx = self.awaiting.iter().position(|sn| sn.port == port).ok_or(())?;
+
+        let mut sn = self.awaiting.remove(idx);
+        sn.state = NodeState::Active;
+
+        self.add_snode(&sn, SpawnStrategy::AlreadyRunning);
+
+        Ok(())
+    }
+
+    fn find_snode_mut(&mut self, port: &str) -> Option<&mut ServiceNode> {
+        self.swarms
+            .iter_mut()
+            .flat_map(|swarm| swarm.nodes.iter_mut())
+            .find(|sn| sn.port == port)
+    }
+
+    /// Take the active node on `port` out of its swarm for a while. Its server
+    /// keeps running; it is up to the storage server to stop serving.
+    pub fn decommission_snode(&mut self, port: &str, credit_blocks: u64) -> Result<(), ()> {
+        let sn = self.find_snode_mut(port).filter(|sn| sn.is_active()).ok_or(())?;
+
+        sn.state = NodeState::Decommissioned { credit_blocks };
+
+        info!("decommissioned snode {} ({} blocks of credit)", port, credit_blocks);
+
+        self.event_log.record(NetworkEvent::Decommissioned {
+            port: port.to_owned(),
+            credit_blocks,
+        });
+
+        Ok(())
+    }
+
+    pub fn recommission_snode(&mut self, port: &str) -> Result<(), ()> {
+        let sn = self.find_snode_mut(port).filter(|sn| !sn.is_active()).ok_or(())?;
+
+        sn.state = NodeState::Active;
+
+        info!("recommissioned snode {}", port);
+
+        self.event_log.record(NetworkEvent::Recommissioned {
+            port: port.to_owned(),
+        });
+
+        Ok(())
+    }
+
+    /// Called for every new block: decommissioned nodes use up
+    /// their credit and are deregistered once it runs out
+    pub fn on_new_block(&mut self) {
+        let mut expired = vec![];
+
+        for swarm in &mut self.swarms {
+            for sn in &mut swarm.nodes {
+                if let NodeState::Decommissioned { credit_blocks } = &mut sn.state {
+                    *credit_blocks = credit_blocks.saturating_sub(1);
+
+                    if *credit_blocks == 0 {
+                        expired.push(sn.port.clone());
+                    }
+                }
+            }
+        }
+
+        for port in expired {
+            info!("snode {} ran out of credit", port);
+
+            let _ = self.deregister_snode(&port);
+        }
+    }
+
+    /// Deregister the node on `port`, shutting down its server
+    pub fn deregister_snode(&mut self, port: &str) -> Result<(), ()> {
+        let (swarm_idx, node_idx) = self
+            .swarms
+            .iter()
+            .enumerate()
+            .find_map(|(swarm_idx, swarm)| {
+                swarm
+                    .nodes
+                    .iter()
+                    .position(|sn| sn.port == port)
+                    .map(|node_idx| (swarm_idx, node_idx))
+            })
+            .ok_or(())?;
+
+        self.drop_snode_at(swarm_idx, node_idx);
+
+        Ok(())
+    }
+
+    /// Start the server of a registered snode, without
+    /// changing the swarms (see `add_snode` for that)
+    pub fn start_snode(&mut self, sn: &ServiceNode) {
+        let child = spawn_service_node(&sn, &self.exe_path, &self.playground).expect("error spawning a service node");
+        self.sn_to_child.insert(sn.clone(), child);
+
+        self.recorder.record(Action::Started { node: sn.clone() });
+    }
+
+    /// Replace the swarms and the nodes awaiting contributions wholesale,
+    /// e.g. with a recorded layout. Nodes are neither started nor stopped.
+    pub fn set_layout(&mut self, swarms: Vec<Swarm>, awaiting: Vec<ServiceNode>) {
+        self.swarms = swarms;
+        self.awaiting = awaiting;
+    }
+
+    /// Go back to an earlier layout, e.g. that of the fork block of a reorg.
+    /// Nodes registered since are stopped, and nodes deregistered since are
+    /// started again. Nodes that are disconnected are left as they are.
+    pub fn restore_layout(&mut self, swarms: Vec<Swarm>, awaiting: Vec<ServiceNode>) {
+        // In layout order, so that runs stay reproducible
+        let registered = |swarms: &[Swarm], awaiting: &[ServiceNode]| -> Vec<ServiceNode> {
+            swarms
+                .iter()
+                .flat_map(|swarm| swarm.nodes.iter())
+                .chain(awaiting.iter())
+                .cloned()
+                .collect()
+        };
+
+        let before = registered(&swarms, &awaiting);
+        let now = registered(&self.swarms, &self.awaiting);
+
+        for sn in now.iter().filter(|sn| !before.contains(sn)) {
+            if self.sn_to_child.contains_key(sn) {
+                info!("reorg: stopping snode {}", sn.port);
+                self.stop(sn);
+            }
+        }
+
+        for sn in before.iter().filter(|sn| !now.contains(sn)) {
+            info!("reorg: restarting snode {}", sn.port);
+            self.start_snode(sn);
+        }
+
+        self.set_layout(swarms, awaiting);
+    }
+
     pub fn create_snode(&mut self, sn: ServiceNode, swarm_idx: usize) {
         info!("NEW SNODE: {}", &sn.port);
-        let child = spawn_service_node(&sn, &self.exe_path).expect("error spawning a service node");
-        self.sn_to_child.insert(sn.clone(), child);
+        self.start_snode(&sn);
+
+        self.event_log.record(NetworkEvent::Registered {
+            port: sn.port.clone(),
+            pubkey: sn.pubkey.clone(),
+            swarm_id: self.swarms[swarm_idx].swarm_id,
+        });
 
         // TODO: use loki rules to determine where the node should go
         self.swarms[swarm_idx].nodes.push(sn);
@@ -478,7 +785,7 @@ impl SwarmManager {
     pub fn quit_children(&mut self) {
         // NOTE: some of these nodes are not running anymore
         print!("Quitting {} nodes...", self.sn_to_child.len());
-        for (sn, child) in &mut self.sn_to_child {
+        for (sn, mut child) in self.sn_to_child.drain() {
             let _ = crate::send_req_to_quit(&sn);
             child.wait().unwrap();
         }
@@ -1,27 +1,119 @@
-use crate::blockchain::{Blockchain, KeyPair, X25519KeyPair, Ed25519KeyPair};
+use crate::blockchain::{Blockchain, BlockRecord, HardFork, KeyPair, X25519KeyPair, Ed25519KeyPair};
+use crate::partition::PartitionKind;
 
 use rand::prelude::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use std::fmt::{self, Debug, Display};
 use std::io::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use crate::client::MessageResponse;
 
+use crate::block_producer::SnEvent;
+use crate::event_log::LoggedEvent;
+use crate::seed;
 use crate::service_node::ServiceNode;
-use crate::swarms::{PubKey, SpawnStrategy, Swarm};
+use crate::swarms::{PubKey, SpawnStrategy, Swarm, SwarmManager};
+use crate::timeline::Action;
+use crate::uptime::NodePings;
 
 use crate::client;
 
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MessageCount {
+    pub tested: u64,
+    pub lost: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LostMessage {
+    pub pubkey: String,
+    pub swarm_id: u64,
+    /// Port of the node that didn't have the message
+    pub node: String,
+    pub data: String,
+    /// Height of the current block when the message was sent
+    pub sent_height: u64,
+    /// Swarm responsible for the pubkey as of `sent_height`
+    pub sent_to_swarm: Option<u64>,
+}
+
+/// The result of `check_messages`. Every message is tested
+/// once for every node in the swarm responsible for it.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MessageReport {
+    pub tested: u64,
+    pub lost: u64,
+    pub per_pubkey: BTreeMap<String, MessageCount>,
+    pub per_swarm: BTreeMap<u64, MessageCount>,
+    /// Number of messages missing on each node, by port
+    pub offending_nodes: BTreeMap<String, u64>,
+    pub lost_messages: Vec<LostMessage>,
+    /// Scenario-specific checks that failed, on top of lost messages
+    pub failed_checks: Vec<String>,
+}
+
+impl MessageReport {
+    pub fn passed(&self) -> bool {
+        self.lost == 0 && self.failed_checks.is_empty()
+    }
+
+    /// Record a failed scenario-specific check
+    pub fn fail_check(&mut self, msg: String) {
+        println!("Check failed: {}", msg);
+        warn!("check failed: {}", msg);
+        self.failed_checks.push(msg);
+    }
+
+    fn record(&mut self, pk: &str, swarm_id: u64, lost: bool) {
+        let lost = lost as u64;
+
+        self.tested += 1;
+        self.lost += lost;
+
+        let by_pk = self.per_pubkey.entry(pk.to_owned()).or_default();
+        by_pk.tested += 1;
+        by_pk.lost += lost;
+
+        let by_swarm = self.per_swarm.entry(swarm_id).or_default();
+        by_swarm.tested += 1;
+        by_swarm.lost += lost;
+    }
+}
+
+/// A message that was accepted by the network
+struct SentMessage {
+    data: String,
+    /// Height of the current block at the time it was sent
+    height: u64,
+}
+
 pub struct TestContext {
     bc: Arc<Mutex<Blockchain>>,
-    messages: HashMap<String, Vec<String>>,
+    messages: HashMap<String, Vec<SentMessage>>,
     latest_port: u16,
     bad_snodes: Vec<ServiceNode>,
     keypair_pool: Vec<(KeyPair, Ed25519KeyPair, X25519KeyPair)>,
     lokid_ports: Vec<u16>,
     rng: StdRng,
+    seed: u64,
+    /// Set once the scenario is over, so that the threads
+    /// it left behind don't act on the next one
+    stopped: Arc<AtomicBool>,
+}
+
+/// Add a message that has just been accepted to the timeline
+fn record_sent(sm: &mut SwarmManager, pk: &str, data: &str) {
+    let pubkey = PubKey::new(pk).expect("invalid pubkey");
+    let port = client::target_node(sm, &pubkey).port.clone();
+
+    sm.recorder.record(Action::MessageSent {
+        pubkey: pk.to_owned(),
+        data: data.to_owned(),
+        port,
+    });
 }
 
 fn is_port_available(port: u16) -> bool {
@@ -77,7 +169,7 @@ impl TestContext {
         keypair_pool
     }
 
-    pub fn new(bc: Arc<Mutex<Blockchain>>, lokid_ports: &[u16]) -> TestContext {
+    pub fn new(bc: Arc<Mutex<Blockchain>>, lokid_ports: &[u16], seed: u64) -> TestContext {
 
         let keypair_pool = TestContext::read_keys();
 
@@ -117,10 +209,38 @@ impl TestContext {
             bad_snodes: vec![],
             keypair_pool,
             lokid_ports: lokid_ports.to_owned(),
-            rng: StdRng::seed_from_u64(0),
+            rng: seed::derive_rng(seed, seed::TEST_CONTEXT),
+            seed,
+            stopped: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Tell the threads started for this scenario (messages, blocks,
+    /// delayed restarts) to stop. They check it while holding the lock
+    /// they act under, so none of them acts after that lock is released.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// A random number generator for a scenario's own choices.
+    /// Different `stream`s give independent generators.
+    pub fn scenario_rng(&self, stream: u64) -> StdRng {
+        seed::derive_rng(seed::derive_seed(self.seed, seed::SCENARIO), stream)
+    }
+
+    /// Ports of the daemons that service nodes can be attached to
+    pub fn lokid_ports(&self) -> &[u16] {
+        &self.lokid_ports
+    }
+
     pub fn snode_count(&self) -> usize {
         let sm = &self.bc.lock().unwrap().swarm_manager;
 
@@ -131,12 +251,24 @@ impl TestContext {
         n
     }
 
+    fn record_message(&mut self, pk: String, data: String, height: u64) {
+        self.messages
+            .entry(pk)
+            .or_insert(vec![])
+            .push(SentMessage { data, height });
+    }
+
     pub fn send_message(&mut self, pk: &str, msg: &str) {
-        if client::send_message_to_pk(&self.bc.lock().unwrap().swarm_manager, pk, msg).is_ok() {
-            self.messages
-                .entry(pk.to_owned())
-                .or_insert(vec![])
-                .push(msg.to_owned());
+        let mut bc = self.bc.lock().unwrap();
+        let height = bc.get_height();
+        let sent = client::send_message_to_pk(&bc.swarm_manager, pk, msg).is_ok();
+        if sent {
+            record_sent(&mut bc.swarm_manager, pk, msg);
+        }
+        drop(bc);
+
+        if sent {
+            self.record_message(pk.to_owned(), msg.to_owned(), height);
         }
     }
 
@@ -155,43 +287,55 @@ impl TestContext {
     }
 
     pub fn send_random_message(&mut self) {
-        if let Ok(msg) =
-            client::send_random_message(&self.bc.lock().unwrap().swarm_manager, &mut self.rng)
-        {
-            self.messages.entry(msg.0).or_insert(vec![]).push(msg.1);
+        let mut bc = self.bc.lock().unwrap();
+        let height = bc.get_height();
+        let res = client::send_random_message(&bc.swarm_manager, &mut self.rng);
+        if let Ok((pk, msg)) = &res {
+            record_sent(&mut bc.swarm_manager, pk, msg);
+        }
+        drop(bc);
+
+        if let Ok((pk, msg)) = res {
+            self.record_message(pk, msg, height);
         }
     }
 
     pub fn send_random_message_to_pk(&mut self, pk: &str) {
-        if let Ok(msg) = client::send_random_message_to_pk(
-            &self.bc.lock().unwrap().swarm_manager,
-            &pk,
-            &mut self.rng,
-        ) {
-            self.messages
-                .entry(pk.to_owned())
-                .or_insert(vec![])
-                .push(msg);
+        let mut bc = self.bc.lock().unwrap();
+        let height = bc.get_height();
+        let res = client::send_random_message_to_pk(&bc.swarm_manager, &pk, &mut self.rng);
+        if let Ok(msg) = &res {
+            record_sent(&mut bc.swarm_manager, pk, msg);
+        }
+        drop(bc);
+
+        if let Ok(msg) = res {
+            self.record_message(pk.to_owned(), msg, height);
         }
     }
 
     /// Check that all previously sent messages are still available
-    pub fn check_messages(&self) {
-        let mut lost_count = 0;
-        let mut messages_tested = 0;
+    pub fn check_messages(&self) -> MessageReport {
+        let mut report = MessageReport::default();
+
+        self.bc.lock().unwrap().swarm_manager.recorder.record(Action::Check);
 
         for key in self.messages.keys() {
             info!("checking messages for PK: {}", key);
 
             let expect_msgs = &self.messages[key];
 
-            let sm = &self.bc.lock().unwrap().swarm_manager;
+            let pk = PubKey::new(&key).unwrap();
+
+            let bc = self.bc.lock().unwrap();
+            let sm = &bc.swarm_manager;
 
-            let swarm_idx = sm.get_swarm_by_pk(&PubKey::new(&key).unwrap());
+            let swarm_idx = sm.get_swarm_by_pk(&pk);
             let swarm = &sm.swarms[swarm_idx as usize];
 
             for sn in &swarm.nodes {
-                if self.bad_snodes.contains(&sn) {
+                // Decommissioned nodes are not expected to keep up
+                if self.bad_snodes.contains(&sn) || !sn.is_active() {
                     continue;
                 };
                 info!(
@@ -212,26 +356,87 @@ impl TestContext {
                 }
 
                 for msg in expect_msgs {
-                    messages_tested += 1;
-
-                    if !got_msgs.contains(&msg) {
-                        warn!("message lost: {}", &msg);
+                    let lost = !got_msgs.contains(&msg.data);
+
+                    if lost {
+                        // Where the message should have landed in the first place,
+                        // tells apart lost deliveries from failed migrations
+                        let sent_to_swarm = bc
+                            .swarm_for_pk_at(&pk, msg.height)
+                            .map(|swarm| swarm.swarm_id);
+
+                        warn!(
+                            "message lost: {} (sent at height {} to swarm {:?})",
+                            &msg.data, msg.height, sent_to_swarm
+                        );
                         warn!("only got: {:?}", &got_msgs);
-                        lost_count += 1;
+                        report.lost_messages.push(LostMessage {
+                            pubkey: key.clone(),
+                            swarm_id: swarm.swarm_id,
+                            node: sn.port.clone(),
+                            data: msg.data.clone(),
+                            sent_height: msg.height,
+                            sent_to_swarm,
+                        });
+                        *report.offending_nodes.entry(sn.port.clone()).or_insert(0) += 1;
                     };
+
+                    report.record(key, swarm.swarm_id, lost);
                 }
             }
         }
 
-        if lost_count == 0 {
+        // Disconnected nodes are not expected to ping either
+        let bc = self.bc.lock().unwrap();
+        for failure in bc.liveness_failures() {
+            if self.bad_snodes.iter().any(|sn| sn.port == failure.port) {
+                continue;
+            }
+
+            report.fail_check(format!(
+                "snode {} hasn't pinged for {} ms ({:?} at height {})",
+                failure.port, failure.silent_ms, failure.action, failure.height
+            ));
+        }
+        drop(bc);
+
+        if report.passed() {
             println!(
                 "Test passed! ({}/{} messages)",
-                messages_tested - lost_count,
-                messages_tested
+                report.tested - report.lost,
+                report.tested
             );
         } else {
-            println!("Messages lost: {}/{}", lost_count, messages_tested);
+            println!("Messages lost: {}/{}", report.lost, report.tested);
+            println!("Nodes missing messages: {:?}", report.offending_nodes);
         }
+
+        report
+    }
+
+    /// Number of messages sent by the test that are
+    /// stored on any of the nodes in `swarm`
+    pub fn count_stored_messages(&self, swarm: &Swarm) -> usize {
+        let mut count = 0;
+
+        for sn in &swarm.nodes {
+            for pk in self.messages.keys() {
+                count += client::request_messages(sn, pk).len();
+            }
+        }
+
+        count
+    }
+
+    /// Make the daemons report a target height `lag_blocks` above
+    /// the current height for `duration`
+    pub fn start_syncing(&mut self, lag_blocks: u64, duration: std::time::Duration) {
+        println!("daemons syncing for {:?}", duration);
+        self.bc.lock().unwrap().start_syncing(lag_blocks, duration);
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.bc.lock().unwrap().is_syncing()
     }
 
     pub fn print_stats(&self) {
@@ -247,6 +452,16 @@ impl TestContext {
 
     /// Swarm manager should decide where to push this SN
     fn add_snode_with_options(&mut self, spawn: SpawnStrategy) -> Option<ServiceNode> {
+        let sn = self.new_snode()?;
+
+        self.bc.lock().unwrap().swarm_manager.add_snode(&sn, spawn);
+
+        Some(sn)
+    }
+
+    /// A node with fresh keys on the next available port,
+    /// not registered yet
+    fn new_snode(&mut self) -> Option<ServiceNode> {
         let mut res = None;
 
         // find available port starting with 5904
@@ -267,8 +482,6 @@ impl TestContext {
                     *lokid_port,
                 );
 
-                self.bc.lock().unwrap().swarm_manager.add_snode(&sn, spawn);
-
                 res = Some(sn);
 
                 self.latest_port = i;
@@ -284,20 +497,56 @@ impl TestContext {
         let _sn = self.add_snode_with_options(SpawnStrategy::Now);
     }
 
+    /// Register a new SN that still awaits contributions, returning its port.
+    /// It only joins a swarm once funded with `fund_snode`.
+    pub fn add_unfunded_snode(&mut self) -> Option<String> {
+        let sn = self.new_snode()?;
+
+        self.bc.lock().unwrap().swarm_manager.register_unfunded(&sn);
+
+        Some(sn.port)
+    }
+
+    pub fn fund_snode(&mut self, port: &str) -> Result<(), ()> {
+        self.bc.lock().unwrap().swarm_manager.fund_snode(port)
+    }
+
+    /// Take the snode on `port` out of its swarm until recommissioned,
+    /// or deregistered after `credit_blocks` blocks
+    pub fn decommission_snode(&mut self, port: &str, credit_blocks: u64) -> Result<(), ()> {
+        println!("decommissioning snode {}", port);
+
+        self.bc
+            .lock()
+            .unwrap()
+            .swarm_manager
+            .decommission_snode(port, credit_blocks)
+    }
+
+    pub fn recommission_snode(&mut self, port: &str) -> Result<(), ()> {
+        println!("recommissioning snode {}", port);
+
+        self.bc.lock().unwrap().swarm_manager.recommission_snode(port)
+    }
+
     /// Register a new SN, but spawn its server instance
     /// only after the specified period of time
     pub fn add_snode_delayed(&mut self, delay_ms: u64) {
         let sn = self.add_snode_with_options(SpawnStrategy::Later);
 
         let bc_copy = self.bc.clone();
+        let stopped = self.stopped.clone();
 
         let _ = std::thread::spawn(move || {
             std::thread::sleep(std::time::Duration::from_millis(delay_ms));
-            bc_copy
-                .lock()
-                .unwrap()
-                .swarm_manager
-                .restore_snode(&sn.expect("spawned node is none"));
+
+            // The scenario is over (it might have panicked)
+            let mut bc = match bc_copy.lock() {
+                Ok(bc) if !stopped.load(Ordering::SeqCst) => bc,
+                _ => return,
+            };
+
+            bc.swarm_manager.restore_snode(&sn.expect("spawned node is none"));
         });
     }
 
@@ -320,19 +569,44 @@ impl TestContext {
     pub fn restart_snode(&mut self, delay_ms: u64) {
         let sn = self.bc.lock().unwrap().swarm_manager.disconnect_snode();
 
+        self.restore_snode_later(sn, delay_ms);
+    }
+
+    /// Same as `restart_snode`, but for the snode listening on `port`
+    pub fn restart_snode_by_port(&mut self, port: &str, delay_ms: u64) -> Result<(), ()> {
+        let sn = self
+            .bc
+            .lock()
+            .unwrap()
+            .swarm_manager
+            .disconnect_snode_by_port(port)
+            .ok_or(())?;
+
+        self.restore_snode_later(sn, delay_ms);
+
+        Ok(())
+    }
+
+    fn restore_snode_later(&self, sn: ServiceNode, delay_ms: u64) {
         println!("restarted snode: {}", sn.port);
         info!("restarted snode: {}", sn.port);
 
         // connect again after a short time
 
         let bc_copy = self.bc.clone();
+        let stopped = self.stopped.clone();
 
         let _ = std::thread::spawn(move || {
             std::thread::sleep(std::time::Duration::from_millis(delay_ms));
 
             // TODO: need to check if the node has been dropped already
             // with tests, so there is no need to restore the node
-            bc_copy.lock().unwrap().swarm_manager.restore_snode(&sn);
+            let mut bc = match bc_copy.lock() {
+                Ok(bc) if !stopped.load(Ordering::SeqCst) => bc,
+                _ => return,
+            };
+
+            bc.swarm_manager.restore_snode(&sn);
         });
     }
 
@@ -359,13 +633,236 @@ impl TestContext {
         bc.swarm_manager.add_swarm(node_data, &self.lokid_ports);
     }
 
-    // TODO: ensure that we call this atomically with corresponding
-    // swarm changes
+    /// Same as `add_swarm`, but with a node attached
+    /// to each of the daemons on `lokid_ports`
+    pub fn add_swarm_attached(&mut self, lokid_ports: &[u16]) -> Vec<ServiceNode> {
+        let nodes: Vec<ServiceNode> = lokid_ports
+            .iter()
+            .map(|&lokid_port| {
+                let mut sn = self.new_snode().expect("no port available");
+                sn.lokid_port = lokid_port;
+                sn
+            })
+            .collect();
+
+        let mut bc = self.bc.lock().unwrap();
+        bc.swarm_manager.add_swarm_nodes(nodes.clone());
+
+        nodes
+    }
+
+    /// Cut the daemon on `daemon` off from (part of) the network for `duration`
+    pub fn start_partition(&mut self, daemon: u16, kind: PartitionKind, duration: std::time::Duration) {
+        println!("partitioning daemon {} for {:?}: {:?}", daemon, duration, kind);
+        self.bc.lock().unwrap().start_partition(daemon, kind, duration);
+    }
+
+    /// Produce a new block with whatever swarm changes have been made since
+    /// the previous one. Use `produce_block` to make the changes part of it.
     pub fn inc_block_height(&mut self) {
-        self.bc.lock().unwrap().inc_block_height();
+        let mut bc = self.bc.lock().unwrap();
+        bc.swarm_manager.on_new_block();
+        bc.enforce_liveness();
+        bc.inc_block_height();
+    }
+
+    /// Apply `events` and produce a new block with them. The daemons only
+    /// serve swarms as of the latest block, so storage servers see
+    /// all of the changes at once, together with the new height.
+    pub fn produce_block(&mut self, events: &[SnEvent]) {
+        let mut reorged = false;
+
+        for event in events {
+            info!("applying {:?}", event);
+
+            match *event {
+                SnEvent::AddSnode => self.add_snode(),
+                SnEvent::AddSwarm(n) => self.add_swarm(n),
+                SnEvent::DropSnode => self.drop_snode(),
+                SnEvent::DisconnectSnode => self.disconnect_snode(),
+                SnEvent::RestartSnode(delay_ms) => self.restart_snode(delay_ms),
+                SnEvent::DissolveSwarm => {
+                    let n = self.bc.lock().unwrap().swarm_manager.swarms.len();
+                    if n > 0 {
+                        let idx = self.rng.gen_range(0, n);
+                        self.dissolve_swarm(idx);
+                    }
+                }
+                SnEvent::Reorg { depth, alt_len } => match self.reorg(depth, alt_len) {
+                    Ok(()) => reorged = true,
+                    Err(()) => warn!("could not replace the last {} blocks", depth),
+                },
+            }
+        }
+
+        // The alternative blocks take the place of this one
+        if !reorged {
+            self.inc_block_height();
+        }
+    }
+
+    /// Replace the last `depth` blocks with `alt_len` alternative ones.
+    /// The alternative chain starts from the layout of the fork block, and
+    /// a new snode is registered before each of its blocks, so it ends up
+    /// with a different swarm layout.
+    pub fn reorg(&mut self, depth: u64, alt_len: u64) -> Result<(), ()> {
+        let mut bc = self.bc.lock().unwrap();
+
+        let fork = bc.history().iter().rev().nth(depth as usize).cloned().ok_or(())?;
+
+        // New snodes need a swarm to join
+        if alt_len > 0 && fork.swarms.is_empty() {
+            return Err(());
+        }
+
+        bc.rollback(depth)?;
+
+        // Undo the membership changes of the orphaned blocks
+        bc.swarm_manager.restore_layout(fork.swarms, fork.awaiting);
+        drop(bc);
+
+        println!("reorg: rolled back {} blocks", depth);
+        info!("reorg: rolled back {} blocks", depth);
+
+        for _ in 0..alt_len {
+            self.add_snode();
+            self.inc_block_height();
+        }
+
+        Ok(())
     }
 
     pub fn get_swarms(&self) -> Vec<Swarm> {
         self.bc.lock().unwrap().get_swarms()
     }
+
+    pub fn get_history(&self) -> Vec<BlockRecord> {
+        self.bc.lock().unwrap().history().to_vec()
+    }
+
+    /// Every service node event so far, oldest first
+    pub fn get_events(&self) -> Vec<LoggedEvent> {
+        self.bc.lock().unwrap().swarm_manager.event_log.events().to_vec()
+    }
+
+    /// The latest uptime proof of every node that has sent one, by port
+    pub fn get_pings(&self) -> Vec<(String, NodePings)> {
+        self.bc
+            .lock()
+            .unwrap()
+            .pings()
+            .nodes()
+            .iter()
+            .map(|(port, pings)| (port.clone(), pings.clone()))
+            .collect()
+    }
+
+    /// Id of the swarm that was responsible for `pk` at `height`
+    pub fn swarm_for_pk_at(&self, pk: &PubKey, height: u64) -> Option<u64> {
+        self.bc
+            .lock()
+            .unwrap()
+            .swarm_for_pk_at(pk, height)
+            .map(|swarm| swarm.swarm_id)
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.bc.lock().unwrap().get_height()
+    }
+
+    /// Hard fork version of the current block
+    pub fn get_hardfork(&self) -> u8 {
+        self.bc.lock().unwrap().current_block().hardfork
+    }
+
+    /// Apply a recorded action (see `timeline::replay`), returning
+    /// the result if it was a check
+    pub fn apply(&mut self, action: &Action) -> Option<MessageReport> {
+        let mut bc = self.bc.lock().unwrap();
+
+        match action {
+            Action::Genesis { .. } => warn!("ignoring a second genesis"),
+            Action::Started { node } => bc.swarm_manager.start_snode(node),
+            Action::Stopped { port } => {
+                // Registration changes only come with the next block
+                if bc.swarm_manager.stop_snode_by_port(port).is_none() {
+                    warn!("snode {} is not running", port);
+                }
+            }
+            Action::Disconnected { port } => match bc.swarm_manager.disconnect_snode_by_port(port) {
+                Some(sn) => self.bad_snodes.push(sn),
+                None => warn!("snode {} is not running", port),
+            },
+            Action::Restored { node } => bc.swarm_manager.restore_snode(node),
+            Action::Block {
+                height,
+                swarms,
+                awaiting,
+            } => {
+                bc.swarm_manager
+                    .set_layout(swarms.clone(), awaiting.clone());
+                bc.inc_block_height();
+
+                if bc.get_height() != *height {
+                    warn!("replayed block {} ended up at height {}", height, bc.get_height());
+                }
+            }
+            Action::Rollback { depth } => {
+                if bc.rollback(*depth).is_err() {
+                    warn!("could not roll back {} blocks", depth);
+                }
+            }
+            Action::HardforkScheduled { height, version } => {
+                let fork = HardFork {
+                    height: *height,
+                    version: *version,
+                };
+                if bc.schedule_hardfork(fork).is_err() {
+                    warn!("could not schedule hard fork {} at height {}", version, height);
+                }
+            }
+            Action::SyncStarted {
+                lag_blocks,
+                duration_ms,
+            } => bc.start_syncing(*lag_blocks, std::time::Duration::from_millis(*duration_ms)),
+            Action::PartitionStarted {
+                daemon,
+                duration_ms,
+                kind,
+            } => bc.start_partition(
+                *daemon,
+                kind.clone(),
+                std::time::Duration::from_millis(*duration_ms),
+            ),
+            Action::MessageSent { pubkey, data, port } => {
+                let height = bc.get_height();
+                if client::send_message(port, pubkey, data).is_ok() {
+                    bc.swarm_manager.recorder.record(Action::MessageSent {
+                        pubkey: pubkey.clone(),
+                        data: data.clone(),
+                        port: port.clone(),
+                    });
+                    drop(bc);
+                    self.record_message(pubkey.clone(), data.clone(), height);
+                } else {
+                    warn!("replayed message to {} was not accepted", port);
+                }
+            }
+            Action::Check => {
+                drop(bc);
+                return Some(self.check_messages());
+            }
+        }
+
+        None
+    }
+
+    pub fn schedule_hardfork(&mut self, height: u64, version: u8) -> Result<(), ()> {
+        println!("scheduling hard fork {} at height {}", version, height);
+
+        self.bc
+            .lock()
+            .unwrap()
+            .schedule_hardfork(HardFork { height, version })
+    }
 }
@@ -1,5 +1,7 @@
-use crate::swarms::PubKey;
-use crate::test_context::TestContext;
+use crate::block_producer::{self, BlockInterval, BlockProducer, EventPlan, SnEvent};
+use crate::partition::PartitionKind;
+use crate::swarms::{PubKey, Swarm};
+use crate::test_context::{MessageReport, TestContext};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
@@ -14,8 +16,7 @@ fn sleep_ms(ms: u64) {
     std::thread::sleep(std::time::Duration::from_millis(ms));
 }
 
-#[allow(dead_code)]
-pub fn async_test(ctx: &Arc<Mutex<TestContext>>) {
+pub fn async_test(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
 
     let mut ctx = ctx.lock().unwrap();
     ctx.add_swarm(1);
@@ -24,7 +25,7 @@ pub fn async_test(ctx: &Arc<Mutex<TestContext>>) {
 
     // make a copy here assuming that swarms are not going to change
 
-    let mut rng = StdRng::seed_from_u64(0);
+    let mut rng = ctx.scenario_rng(0);
     let ip = ctx.get_swarms()[0].nodes[0]
         .port
         .clone();
@@ -69,10 +70,11 @@ pub fn async_test(ctx: &Arc<Mutex<TestContext>>) {
 
     println!("saved total: {}", saved_total.lock().unwrap());
     println!("failed total: {}", failed_total.lock().unwrap());
+
+    None
 }
 
-#[allow(dead_code)]
-pub fn one_node_big_data(ctx: &Arc<Mutex<TestContext>>) {
+pub fn one_node_big_data(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
     ctx.add_swarm(1);
 
@@ -98,9 +100,9 @@ pub fn one_node_big_data(ctx: &Arc<Mutex<TestContext>>) {
         let failed_total = failed_total.clone();
         let saved_total = saved_total.clone();
 
-        let t = std::thread::spawn(move || {
-            let mut rng = StdRng::seed_from_u64(i);
+        let mut rng = ctx.scenario_rng(i);
 
+        let t = std::thread::spawn(move || {
             let pk = PubKey::gen_random(&mut rng).to_string();
 
             let mut failed = 0;
@@ -136,11 +138,14 @@ pub fn one_node_big_data(ctx: &Arc<Mutex<TestContext>>) {
 
     println!("saved total: {}", saved_total.lock().unwrap());
     println!("failed total: {}", failed_total.lock().unwrap());
+
+    None
 }
 
-#[allow(dead_code)]
-pub fn long_polling(ctx: &Arc<Mutex<TestContext>>) {
-    let mut rng = StdRng::seed_from_u64(0);
+// Note: when testing long-polling, need to
+// modify client request to use the right header
+pub fn long_polling(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
+    let mut rng = ctx.lock().unwrap().scenario_rng(0);
     let pk = PubKey::gen_random(&mut rng);
 
     {
@@ -174,10 +179,11 @@ pub fn long_polling(ctx: &Arc<Mutex<TestContext>>) {
     // send another message in 2s
     sleep_ms(2000);
     ctx.lock().unwrap().send_random_message_to_pk(&pk.to_string());
+
+    None
 }
 
-#[allow(dead_code)]
-pub fn test_bootstrapping_peer_big_data(ctx: &Arc<Mutex<TestContext>>) {
+pub fn test_bootstrapping_peer_big_data(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
 
     ctx.add_swarm(1);
@@ -192,11 +198,10 @@ pub fn test_bootstrapping_peer_big_data(ctx: &Arc<Mutex<TestContext>>) {
     ctx.inc_block_height();
 
     sleep_ms(10000);
-    ctx.check_messages();
+    Some(ctx.check_messages())
 }
 
-#[allow(dead_code)]
-pub fn test_bootstrapping_swarm_big_data(ctx: &Arc<Mutex<TestContext>>) {
+pub fn test_bootstrapping_swarm_big_data(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
 
     ctx.add_swarm(1);
@@ -213,12 +218,11 @@ pub fn test_bootstrapping_swarm_big_data(ctx: &Arc<Mutex<TestContext>>) {
     ctx.inc_block_height();
 
     sleep_ms(10000);
-    ctx.check_messages();
+    Some(ctx.check_messages())
 }
 
 /// 0. Most basic test: send a message to a single snode and check
-#[allow(dead_code)]
-pub fn single_node_one_message(ctx: &Arc<Mutex<TestContext>>) {
+pub fn single_node_one_message(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
 
     ctx.add_swarm(1);
@@ -232,12 +236,11 @@ pub fn single_node_one_message(ctx: &Arc<Mutex<TestContext>>) {
 
     sleep_ms(2000);
 
-    ctx.check_messages();
+    Some(ctx.check_messages())
 }
 
 /// 1. Test that nodes relay messages to other swarm members
-#[allow(dead_code)]
-pub fn single_swarm_one_message(ctx: &Arc<Mutex<TestContext>>) {
+pub fn single_swarm_one_message(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
 
     ctx.add_swarm(3);
@@ -251,12 +254,11 @@ pub fn single_swarm_one_message(ctx: &Arc<Mutex<TestContext>>) {
 
     sleep_ms(2000);
 
-    ctx.check_messages();
+    Some(ctx.check_messages())
 }
 
 /// 2. Test adding an additional snode to a swarm
-#[allow(dead_code)]
-pub fn sinlge_swarm_joined(ctx: &Arc<Mutex<TestContext>>) {
+pub fn sinlge_swarm_joined(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
 
     ctx.add_swarm(3);
@@ -279,12 +281,11 @@ pub fn sinlge_swarm_joined(ctx: &Arc<Mutex<TestContext>>) {
 
     sleep_ms(3000);
 
-    ctx.check_messages();
+    Some(ctx.check_messages())
 }
 
 /// 3. Test new swarm detection
-#[allow(dead_code)]
-pub fn swarm_splitting(ctx: &Arc<Mutex<TestContext>>) {
+pub fn swarm_splitting(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
 
     ctx.add_swarm(3);
@@ -307,12 +308,11 @@ pub fn swarm_splitting(ctx: &Arc<Mutex<TestContext>>) {
 
     sleep_ms(2000);
 
-    ctx.check_messages();
+    Some(ctx.check_messages())
 }
 
 /// 4. Test multiple swarms with no changes to the swarm composition
-#[allow(dead_code)]
-pub fn multiple_swarms_static(ctx: &Arc<Mutex<TestContext>>) {
+pub fn multiple_swarms_static(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
 
     ctx.add_swarm(2);
@@ -332,12 +332,11 @@ pub fn multiple_swarms_static(ctx: &Arc<Mutex<TestContext>>) {
 
     sleep_ms(2000);
 
-    ctx.check_messages();
+    Some(ctx.check_messages())
 }
 
 /// Test that a dissolving swarm will push its data to other swarms
-#[allow(dead_code)]
-pub fn test_dissolving(ctx: &Arc<Mutex<TestContext>>) {
+pub fn test_dissolving(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
 
     ctx.add_swarm(1);
@@ -390,12 +389,11 @@ pub fn test_dissolving(ctx: &Arc<Mutex<TestContext>>) {
     );
 
     sleep_ms(2000);
-    ctx.check_messages();
+    Some(ctx.check_messages())
 }
 
 /// 4. Test a node going offline without updating the swarm list
-#[allow(dead_code)]
-pub fn test_retry_singles(ctx: &Arc<Mutex<TestContext>>) {
+pub fn test_retry_singles(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
 
     ctx.add_swarm(2);
@@ -418,11 +416,10 @@ pub fn test_retry_singles(ctx: &Arc<Mutex<TestContext>>) {
     println!("Waiting for a snode to come back online and be tested again...");
     sleep_ms(4000);
 
-    ctx.check_messages();
+    Some(ctx.check_messages())
 }
 
-#[allow(dead_code)]
-pub fn test_retry_batches(ctx: &Arc<Mutex<TestContext>>) {
+pub fn test_retry_batches(ctx: &Arc<Mutex<TestContext>>) -> Option<MessageReport> {
     let mut ctx = ctx.lock().unwrap();
 
     // 1. Create a swarm with a single node
@@ -449,7 +446,7 @@ pub fn test_retry_batches(ctx: &Arc<Mutex<TestContext>>) {
     println!("Waiting for a snode to come back online and be tested again...");
     sleep_ms(4000);
 
-    ctx.check_messages();
+    Some(ctx.check_messages())
 }
 
 fn gen_rand_pubkeys(n: u32, mut rng: &mut StdRng) -> Vec<PubKey> {
@@ -463,14 +460,29 @@ fn gen_rand_pubkeys(n: u32, mut rng: &mut StdRng) -> Vec<PubKey> {
     pks
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct TestOptions {
     pub reliable_snodes: bool,
     pub duration: std::time::Duration,
     pub block_interval: std::time::Duration,
+    /// Blocks come every `block_interval` plus or minus up to this much
+    pub block_jitter: std::time::Duration,
     pub message_interval: std::time::Duration,
 }
 
+impl Default for TestOptions {
+    fn default() -> TestOptions {
+        // Note: currently the test works fine to 15 minutes
+        TestOptions {
+            reliable_snodes: true,
+            duration: std::time::Duration::from_secs(40),
+            block_interval: std::time::Duration::from_secs(2),
+            block_jitter: std::time::Duration::from_millis(0),
+            message_interval: std::time::Duration::from_millis(200),
+        }
+    }
+}
+
 fn generate_messages_thread(
     ctx: &Arc<Mutex<TestContext>>,
     pks: &Vec<PubKey>,
@@ -490,9 +502,14 @@ fn generate_messages_thread(
 
             let pk = pks.choose(&mut rng_clone).unwrap();
 
-            ctx.lock()
-                .unwrap()
-                .send_random_message_to_pk(&pk.to_string());
+            // The scenario is over (it might have panicked)
+            let mut ctx = match ctx.lock() {
+                Ok(ctx) if !ctx.is_stopped() => ctx,
+                _ => break,
+            };
+
+            ctx.send_random_message_to_pk(&pk.to_string());
+            drop(ctx);
 
             if !running.load(Ordering::SeqCst) {
                 break;
@@ -501,62 +518,91 @@ fn generate_messages_thread(
     })
 }
 
-// Should this be a separate thread?
-fn generate_blocks(
-    ctx: &Arc<Mutex<TestContext>>,
-    opt: TestOptions,
-    rng: &mut StdRng,
-    running_flag: &Arc<AtomicBool>,
-) {
-    // Every iteration in this loop corresponds to a block
-    for i in 0.. {
-        println!("iteration: {}", i);
-        info!("iteration: {}", i);
-        // how much to wait until the next block
-        // let ms = rng.gen_range(500, 2000);
-        std::thread::sleep(opt.block_interval);
+/// Random SN events every block, keeping the number of snodes around 10-50
+fn random_events(opt: &TestOptions, rng: &StdRng) -> EventPlan {
+    let reliable_snodes = opt.reliable_snodes;
+    let mut rng = rng.clone();
 
-        let mut ctx = ctx.lock().unwrap();
+    Box::new(move |ctx| {
+        let mut events = vec![];
+        let mut count = ctx.snode_count();
 
         // deregister some
-        if ctx.snode_count() > 10 {
+        if count > 10 {
             let n = rng.gen_range(0, 3);
             for _ in 0..n {
-                ctx.drop_snode();
+                events.push(SnEvent::DropSnode);
             }
+            count -= n;
 
             // IMPORTANT: it seems that setting this to a long delay
             // prevents SN from recovering. Could be because they miss
             // a few block updates
-            if !opt.reliable_snodes {
-                ctx.restart_snode(1000);
+            if !reliable_snodes {
+                events.push(SnEvent::RestartSnode(1000));
             }
         }
 
         // register some
-        if ctx.snode_count() < 50 {
+        if count < 50 {
             let n = rng.gen_range(0, 3);
             for _ in 0..n {
-                ctx.add_snode();
+                events.push(SnEvent::AddSnode);
             }
         }
 
-        ctx.inc_block_height();
-
-        println!("swarms: {:?}", *ctx);
+        events
+    })
+}
 
-        // release the lock
-        drop(ctx);
+/// Produce blocks following `plan` until `running_flag` is cleared
+fn produce_blocks(
+    ctx: &Arc<Mutex<TestContext>>,
+    opt: &TestOptions,
+    plan: EventPlan,
+    running_flag: &Arc<AtomicBool>,
+) {
+    let producer = BlockProducer::start(ctx, BlockInterval::from_options(opt), plan);
 
-        if !running_flag.load(Ordering::SeqCst) {
-            break;
-        }
+    while running_flag.load(Ordering::SeqCst) {
+        sleep_ms(100);
     }
+
+    producer.stop();
+}
+
+/// Send messages to `pks` and produce blocks following `plan` for the
+/// duration of the test, then give the last messages time to propagate
+fn produce_blocks_with_messages(
+    ctx: &Arc<Mutex<TestContext>>,
+    opt: &TestOptions,
+    plan: EventPlan,
+    pks: &Vec<PubKey>,
+    rng: &StdRng,
+) {
+    let running_flag = Arc::new(AtomicBool::new(true));
+
+    let duration = opt.duration;
+    let running = running_flag.clone();
+    let timer_thread = std::thread::spawn(move || {
+        std::thread::sleep(duration);
+        running.store(false, Ordering::SeqCst);
+    });
+
+    let message_thread = generate_messages_thread(ctx, pks, opt.clone(), rng, &running_flag);
+
+    produce_blocks(ctx, opt, plan, &running_flag);
+
+    timer_thread.join().unwrap();
+    message_thread.join().unwrap();
+
+    // wait for the duration of one block to
+    // make sure all message have been propagated
+    std::thread::sleep(opt.block_interval);
 }
 
 
-#[allow(dead_code)]
-pub fn test_real_messenger(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) {
+pub fn test_real_messenger(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) -> Option<MessageReport> {
 
     let running_flag = Arc::new(AtomicBool::new(true));
 
@@ -572,131 +618,496 @@ pub fn test_real_messenger(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) {
     // Add a single swarm with `NODE_COUNT` nodes
     ctx.lock().unwrap().add_swarm(NODE_COUNT);
 
-    for _ in 0.. {
+    produce_blocks(ctx, opt, block_producer::no_events(), &running_flag);
 
-        std::thread::sleep(opt.block_interval);
+    timer_thread.join().unwrap();
 
-        ctx.lock().unwrap().inc_block_height();
+    None
+}
+
+pub fn test_persistent_blocks(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) -> Option<MessageReport> {
+
+    let mut rng = ctx.lock().unwrap().scenario_rng(0);
+
+    // messenger keys
+    let pks = gen_rand_pubkeys(100, &mut rng);
+
+    ctx.lock().unwrap().add_swarm(2);
+
+    produce_blocks_with_messages(ctx, opt, block_producer::no_events(), &pks, &rng);
+
+    ctx.lock().unwrap().print_stats();
+    Some(ctx.lock().unwrap().check_messages())
+}
+
+/// Every few blocks the chain is reorganised, replacing the last
+/// blocks with an alternative sequence with a different swarm layout
+pub fn test_reorg(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) -> Option<MessageReport> {
+    let mut rng = ctx.lock().unwrap().scenario_rng(0);
+
+    let pks = gen_rand_pubkeys(100, &mut rng);
 
-        if !running_flag.load(Ordering::SeqCst) {
-            break;
+    ctx.lock().unwrap().add_swarm(3);
+    ctx.lock().unwrap().add_swarm(3);
+
+    let mut reorg_rng = rng.clone();
+    let mut block = 0;
+    let plan: EventPlan = Box::new(move |_| {
+        block += 1;
+
+        if block % 5 == 0 {
+            let depth = reorg_rng.gen_range(1, 4);
+            // The alternative chain is longer, so it would win
+            vec![SnEvent::Reorg { depth, alt_len: depth + 1 }]
+        } else {
+            vec![]
         }
-    }
+    });
 
-    timer_thread.join().unwrap();
+    produce_blocks_with_messages(ctx, opt, plan, &pks, &rng);
+
+    ctx.lock().unwrap().print_stats();
+    Some(ctx.lock().unwrap().check_messages())
 }
 
-#[allow(dead_code)]
-pub fn test_persistent_blocks(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) {
+/// Schedule the next hard fork version for about halfway through the test
+fn schedule_hardfork_midway(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) {
+    let blocks = opt.duration.as_millis() / opt.block_interval.as_millis().max(1);
+    let blocks = std::cmp::max(blocks as u64 / 2, 1);
 
-    let mut rng = StdRng::seed_from_u64(0);
+    let mut ctx = ctx.lock().unwrap();
+
+    let height = ctx.get_height() + blocks;
+    let version = ctx.get_hardfork() + 1;
+
+    ctx.schedule_hardfork(height, version)
+        .expect("could not schedule a hard fork");
+}
+
+/// Static swarms, with a hard fork in the middle of the test
+pub fn test_hardfork(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) -> Option<MessageReport> {
+    let mut rng = ctx.lock().unwrap().scenario_rng(0);
 
-    // messenger keys
     let pks = gen_rand_pubkeys(100, &mut rng);
 
-    let running_flag = Arc::new(AtomicBool::new(true));
+    ctx.lock().unwrap().add_swarm(3);
+    ctx.lock().unwrap().add_swarm(3);
 
-    let duration = opt.duration;
-    let running = running_flag.clone();
-    let timer_thread = std::thread::spawn(move || {
-        std::thread::sleep(duration);
-        running.store(false, Ordering::SeqCst);
-    });
+    schedule_hardfork_midway(ctx, opt);
 
-    ctx.lock().unwrap().add_swarm(2);
+    produce_blocks_with_messages(ctx, opt, block_producer::no_events(), &pks, &rng);
 
-    let message_thread = generate_messages_thread(&ctx, &pks, opt.clone(), &rng, &running_flag);
+    Some(ctx.lock().unwrap().check_messages())
+}
 
-    for i in 0.. {
+/// Same as `test_blocks`, with a hard fork in the middle of the test
+pub fn test_hardfork_blocks(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) -> Option<MessageReport> {
+    let mut rng = ctx.lock().unwrap().scenario_rng(0);
 
-        std::thread::sleep(opt.block_interval);
+    let pks = gen_rand_pubkeys(100, &mut rng);
+
+    ctx.lock().unwrap().add_swarm(3);
 
-        println!("iteration: {}", i);
-        info!("iteration: {}", i);
+    schedule_hardfork_midway(ctx, opt);
 
+    produce_blocks_with_messages(ctx, opt, random_events(opt, &rng), &pks, &rng);
+
+    ctx.lock().unwrap().print_stats();
+    Some(ctx.lock().unwrap().check_messages())
+}
+
+/// While the daemons report themselves as syncing, storage servers
+/// shouldn't act on swarm changes, e.g. push their data to a new swarm
+pub fn test_syncing(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) -> Option<MessageReport> {
+    const SYNC_LAG: u64 = 10;
+
+    let mut rng = ctx.lock().unwrap().scenario_rng(0);
+
+    let pks = gen_rand_pubkeys(100, &mut rng);
+
+    ctx.lock().unwrap().add_swarm(3);
+    ctx.lock().unwrap().inc_block_height();
+
+    // give SNs some time to initialize their servers
+    std::thread::sleep(opt.block_interval);
+
+    for pk in &pks {
+        ctx.lock()
+            .unwrap()
+            .send_random_message_to_pk(&pk.to_string());
+    }
+
+    let sync_duration = opt.block_interval * 6;
+    ctx.lock().unwrap().start_syncing(SYNC_LAG, sync_duration);
+    let sync_start = std::time::Instant::now();
+
+    // Some of the pubkeys now belong to the new swarm
+    ctx.lock().unwrap().add_swarm(3);
+    ctx.lock().unwrap().inc_block_height();
+
+    let new_swarm = ctx.lock().unwrap().get_swarms().pop().unwrap();
+
+    // Give the SNs plenty of time to (wrongly) push data to the new swarm
+    for _ in 0..3 {
+        std::thread::sleep(opt.block_interval);
         ctx.lock().unwrap().inc_block_height();
+    }
 
-        if !running_flag.load(Ordering::SeqCst) {
-            break;
-        }
+    let still_syncing = ctx.lock().unwrap().is_syncing();
+    let pushed = ctx.lock().unwrap().count_stored_messages(&new_swarm);
+
+    // Wait for the daemons to sync, and for the data to be migrated
+    if let Some(left) = sync_duration.checked_sub(sync_start.elapsed()) {
+        std::thread::sleep(left);
+    }
+    ctx.lock().unwrap().inc_block_height();
+    std::thread::sleep(opt.block_interval * 2);
+
+    let mut report = ctx.lock().unwrap().check_messages();
+
+    if !still_syncing {
+        report.fail_check("the sync phase ended too early to check anything".to_owned());
+    } else if pushed > 0 {
+        report.fail_check(format!(
+            "{} messages pushed to swarm {} while the daemons were syncing",
+            pushed, new_swarm.swarm_id
+        ));
     }
 
-    timer_thread.join().unwrap();
-    message_thread.join().unwrap();
+    Some(report)
+}
 
-    // wait for the duration of one block to
-    // make sure all message have been propagated
+fn send_to_all(ctx: &Arc<Mutex<TestContext>>, pks: &[PubKey]) {
+    for pk in pks {
+        ctx.lock()
+            .unwrap()
+            .send_random_message_to_pk(&pk.to_string());
+    }
+}
+
+/// A node is decommissioned and later recommissioned: it shouldn't store
+/// new messages while out of its swarm, and should catch up once back
+pub fn test_decommission(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) -> Option<MessageReport> {
+    let mut rng = ctx.lock().unwrap().scenario_rng(0);
+
+    let pks = gen_rand_pubkeys(20, &mut rng);
+
+    ctx.lock().unwrap().add_swarm(4);
+    ctx.lock().unwrap().inc_block_height();
+
+    // give SNs some time to initialize their servers
     std::thread::sleep(opt.block_interval);
 
-    ctx.lock().unwrap().print_stats();
-    ctx.lock().unwrap().check_messages();
+    send_to_all(ctx, &pks);
+
+    let swarm = ctx.lock().unwrap().get_swarms()[0].clone();
+    let node = swarm.nodes.last().unwrap().clone();
+    let decommissioned = Swarm {
+        swarm_id: swarm.swarm_id,
+        nodes: vec![node.clone()],
+    };
+
+    // Let the messages propagate within the swarm
+    std::thread::sleep(opt.block_interval);
+    let stored_before = ctx.lock().unwrap().count_stored_messages(&decommissioned);
+
+    ctx.lock()
+        .unwrap()
+        .decommission_snode(&node.port, 1000)
+        .expect("could not decommission a snode");
+    ctx.lock().unwrap().inc_block_height();
+    std::thread::sleep(opt.block_interval);
+
+    send_to_all(ctx, &pks);
+    std::thread::sleep(opt.block_interval);
+
+    let stored_while_decommissioned = ctx.lock().unwrap().count_stored_messages(&decommissioned);
+
+    ctx.lock()
+        .unwrap()
+        .recommission_snode(&node.port)
+        .expect("could not recommission a snode");
+    ctx.lock().unwrap().inc_block_height();
+
+    // Give the node some time to catch up with its swarm
+    std::thread::sleep(opt.block_interval * 3);
+
+    // The recommissioned node is checked along with the rest of its swarm
+    let mut report = ctx.lock().unwrap().check_messages();
+
+    if stored_while_decommissioned > stored_before {
+        report.fail_check(format!(
+            "{} messages stored on snode {} while decommissioned",
+            stored_while_decommissioned - stored_before,
+            node.port
+        ));
+    }
+
+    Some(report)
 }
 
-/// `reliable` determines whether nodes can disconnect from time
-/// to time for a short period of time
-#[allow(dead_code)]
-pub fn test_blocks(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) {
-    let mut rng = StdRng::seed_from_u64(0);
+/// A swarm with nodes attached to two different daemons, one of which only
+/// sees its own nodes while the swarm grows. Once the partition is over,
+/// every node in the swarm should have every message.
+pub fn test_partition(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) -> Option<MessageReport> {
+    let mut rng = ctx.lock().unwrap().scenario_rng(0);
 
-    let pks = gen_rand_pubkeys(100, &mut rng);
+    let pks = gen_rand_pubkeys(20, &mut rng);
 
-    ctx.lock().unwrap().add_swarm(3);
+    let daemons = ctx.lock().unwrap().lokid_ports().to_vec();
+    if daemons.len() < 2 {
+        println!("test_partition needs at least two daemons");
+        return None;
+    }
+    let (a, b) = (daemons[0], daemons[1]);
 
-    // PART I
-    {
-        let running_flag = Arc::new(AtomicBool::new(true));
+    ctx.lock().unwrap().add_swarm_attached(&[a, a, b, b]);
+    ctx.lock().unwrap().inc_block_height();
 
-        let running = running_flag.clone();
+    // give SNs some time to initialize their servers
+    std::thread::sleep(opt.block_interval);
 
-        let duration = opt.duration;
-        let timer_thread = std::thread::spawn(move || {
-            std::thread::sleep(duration);
-            running.store(false, Ordering::SeqCst);
-        });
+    send_to_all(ctx, &pks);
+
+    let duration = opt.block_interval * 4;
+    ctx.lock()
+        .unwrap()
+        .start_partition(b, PartitionKind::Subset { daemons: vec![] }, duration);
+    std::thread::sleep(opt.block_interval);
+
+    // The daemons disagree about the new member too
+    ctx.lock().unwrap().add_snode();
+    ctx.lock().unwrap().inc_block_height();
+    std::thread::sleep(opt.block_interval);
 
-        let message_thread = generate_messages_thread(&ctx, &pks, opt.clone(), &rng, &running_flag);
+    send_to_all(ctx, &pks);
 
-        generate_blocks(&ctx, opt.clone(), &mut rng, &running_flag);
+    // Wait for the partition to be over and the swarm to settle
+    std::thread::sleep(duration + opt.block_interval * 3);
 
-        timer_thread.join().unwrap();
-        message_thread.join().unwrap();
+    Some(ctx.lock().unwrap().check_messages())
+}
+
+/// A decommissioned node runs out of credit and gets deregistered, while
+/// a node that was awaiting contributions gets funded and takes its place
+pub fn test_decommission_expiry(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) -> Option<MessageReport> {
+    const CREDIT_BLOCKS: u64 = 3;
+
+    let mut rng = ctx.lock().unwrap().scenario_rng(0);
+
+    let pks = gen_rand_pubkeys(20, &mut rng);
+
+    ctx.lock().unwrap().add_swarm(3);
+    let unfunded = ctx
+        .lock()
+        .unwrap()
+        .add_unfunded_snode()
+        .expect("could not add a snode");
+    ctx.lock().unwrap().inc_block_height();
 
-        // wait for the duration of one block to
-        // make sure all message have been propagated
+    // give SNs some time to initialize their servers
+    std::thread::sleep(opt.block_interval);
+
+    send_to_all(ctx, &pks);
+    std::thread::sleep(opt.block_interval);
+
+    let port = ctx.lock().unwrap().get_swarms()[0].nodes[0].port.clone();
+
+    ctx.lock()
+        .unwrap()
+        .decommission_snode(&port, CREDIT_BLOCKS)
+        .expect("could not decommission a snode");
+
+    for _ in 0..=CREDIT_BLOCKS {
+        ctx.lock().unwrap().inc_block_height();
         std::thread::sleep(opt.block_interval);
+    }
 
-        ctx.lock().unwrap().print_stats();
-        ctx.lock().unwrap().check_messages();
+    let still_registered = ctx
+        .lock()
+        .unwrap()
+        .get_swarms()
+        .iter()
+        .flat_map(|swarm| swarm.nodes.iter())
+        .any(|sn| sn.port == port);
+
+    ctx.lock()
+        .unwrap()
+        .fund_snode(&unfunded)
+        .expect("could not fund a snode");
+    ctx.lock().unwrap().inc_block_height();
+
+    // Give the new node some time to get the swarm's data
+    std::thread::sleep(opt.block_interval * 3);
+
+    let mut report = ctx.lock().unwrap().check_messages();
+
+    if still_registered {
+        report.fail_check(format!(
+            "snode {} still registered after running out of credit",
+            port
+        ));
     }
 
+    Some(report)
+}
 
-    // // PART II
-    // {
-    //     let running_flag = Arc::new(AtomicBool::new(true));
+/// `reliable` determines whether nodes can disconnect from time
+/// to time for a short period of time
+pub fn test_blocks(ctx: &Arc<Mutex<TestContext>>, opt: &TestOptions) -> Option<MessageReport> {
+    let mut rng = ctx.lock().unwrap().scenario_rng(0);
 
-    //     let running = running_flag.clone();
+    let pks = gen_rand_pubkeys(100, &mut rng);
 
-    //     let duration = opt.duration;
-    //     // TODO: use a timer instead
-    //     let timer_thread = std::thread::spawn(move || {
-    //         std::thread::sleep(duration);
-    //         running.store(false, Ordering::SeqCst);
-    //     });
+    ctx.lock().unwrap().add_swarm(3);
 
-    //     let message_thread = generate_messages_thread(&ctx, &pks, opt.clone(), &rng, &running_flag);
+    // PART I
+    let report = {
+        produce_blocks_with_messages(ctx, opt, random_events(opt, &rng), &pks, &rng);
 
-    //     generate_blocks(&ctx, opt.clone(), &mut rng, &running_flag);
+        ctx.lock().unwrap().print_stats();
+        ctx.lock().unwrap().check_messages()
+    };
 
-    //     timer_thread.join().unwrap();
-    //     message_thread.join().unwrap();
 
-    //     // wait for the duration of one block to
-    //     // make sure all message have been propagated
-    //     std::thread::sleep(opt.block_interval);
+    // // PART II
+    // {
+    //     produce_blocks_with_messages(ctx, opt, random_events(opt, &rng), &pks, &rng);
 
     //     ctx.lock().unwrap().print_stats();
     //     ctx.lock().unwrap().check_messages();
     // }
 
+    Some(report)
+}
+
+/// A test that can be selected by name from the command line
+pub struct Scenario {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Returns the result of the final message check,
+    /// or `None` if the scenario doesn't check messages
+    pub run: fn(&Arc<Mutex<TestContext>>, &TestOptions) -> Option<MessageReport>,
+}
 
+pub const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "async_test",
+        description: "Send a burst of messages to a single node asynchronously",
+        run: |ctx, _| async_test(ctx),
+    },
+    Scenario {
+        name: "one_node_big_data",
+        description: "Bombard a single node with messages from many threads",
+        run: |ctx, _| one_node_big_data(ctx),
+    },
+    Scenario {
+        name: "long_polling",
+        description: "Retrieve messages for a pubkey repeatedly while new ones arrive",
+        run: |ctx, _| long_polling(ctx),
+    },
+    Scenario {
+        name: "test_bootstrapping_peer_big_data",
+        description: "Add a node to a swarm that already stores a lot of data",
+        run: |ctx, _| test_bootstrapping_peer_big_data(ctx),
+    },
+    Scenario {
+        name: "test_bootstrapping_swarm_big_data",
+        description: "Add a new swarm while the existing one stores a lot of data",
+        run: |ctx, _| test_bootstrapping_swarm_big_data(ctx),
+    },
+    Scenario {
+        name: "single_node_one_message",
+        description: "Send a message to a single node and check it",
+        run: |ctx, _| single_node_one_message(ctx),
+    },
+    Scenario {
+        name: "single_swarm_one_message",
+        description: "Test that nodes relay messages to other swarm members",
+        run: |ctx, _| single_swarm_one_message(ctx),
+    },
+    Scenario {
+        name: "sinlge_swarm_joined",
+        description: "Test adding an additional node to a swarm",
+        run: |ctx, _| sinlge_swarm_joined(ctx),
+    },
+    Scenario {
+        name: "swarm_splitting",
+        description: "Test new swarm detection",
+        run: |ctx, _| swarm_splitting(ctx),
+    },
+    Scenario {
+        name: "multiple_swarms_static",
+        description: "Multiple swarms with no changes to the swarm composition",
+        run: |ctx, _| multiple_swarms_static(ctx),
+    },
+    Scenario {
+        name: "test_dissolving",
+        description: "Test that a dissolving swarm pushes its data to other swarms",
+        run: |ctx, _| test_dissolving(ctx),
+    },
+    Scenario {
+        name: "test_retry_singles",
+        description: "A node goes offline for a while without updating the swarm list",
+        run: |ctx, _| test_retry_singles(ctx),
+    },
+    Scenario {
+        name: "test_retry_batches",
+        description: "A registered node comes online only after messages have been sent",
+        run: |ctx, _| test_retry_batches(ctx),
+    },
+    Scenario {
+        name: "test_blocks",
+        description: "Random SN events every block while messages are sent continuously",
+        run: test_blocks,
+    },
+    Scenario {
+        name: "test_persistent_blocks",
+        description: "Static swarms, new blocks and messages sent continuously",
+        run: test_persistent_blocks,
+    },
+    Scenario {
+        name: "test_reorg",
+        description: "Messages sent continuously while the chain is reorganised every few blocks",
+        run: test_reorg,
+    },
+    Scenario {
+        name: "test_hardfork",
+        description: "Static swarms crossing a hard fork in the middle of the test",
+        run: test_hardfork,
+    },
+    Scenario {
+        name: "test_hardfork_blocks",
+        description: "Random SN events every block, crossing a hard fork in the middle of the test",
+        run: test_hardfork_blocks,
+    },
+    Scenario {
+        name: "test_syncing",
+        description: "A new swarm appears while the daemons are syncing, data should only move once synced",
+        run: test_syncing,
+    },
+    Scenario {
+        name: "test_decommission",
+        description: "A node is decommissioned and recommissioned, catching up with its swarm",
+        run: test_decommission,
+    },
+    Scenario {
+        name: "test_decommission_expiry",
+        description: "A decommissioned node runs out of credit, a newly funded node takes its place",
+        run: test_decommission_expiry,
+    },
+    Scenario {
+        name: "test_partition",
+        description: "Two daemons disagree about the members of a swarm for a while",
+        run: test_partition,
+    },
+    Scenario {
+        name: "test_real_messenger",
+        description: "A single swarm producing blocks, to be used with a real messenger",
+        run: test_real_messenger,
+    },
+];
+
+pub fn find_scenario(name: &str) -> Option<&'static Scenario> {
+    SCENARIOS.iter().find(|s| s.name == name)
 }
@@ -0,0 +1,183 @@
+use crate::blockchain::{ChainParams, HardFork, SyncPhase};
+use crate::config::DaemonConfig;
+use crate::partition::{Partition, PartitionKind};
+use crate::service_node::ServiceNode;
+use crate::swarms::Swarm;
+use crate::test_context::{MessageReport, TestContext};
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Everything that needs to be done again to reproduce a run. Choices
+/// are recorded already made (which node, which swarm layout, which
+/// payload), so replaying doesn't depend on any random numbers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "action")]
+pub enum Action {
+    /// Always the first entry
+    Genesis {
+        seed: u64,
+        hardforks: Vec<HardFork>,
+        sync_phases: Vec<SyncPhase>,
+        partitions: Vec<Partition>,
+        daemons: Vec<DaemonConfig>,
+    },
+    /// The server of a newly registered node was started
+    Started { node: ServiceNode },
+    /// The server of a deregistered node was shut down
+    Stopped { port: String },
+    /// Fault: the server was shut down, but the node is still registered
+    Disconnected { port: String },
+    /// The server of a disconnected node was started again
+    Restored { node: ServiceNode },
+    /// A new block with this swarm layout
+    Block {
+        height: u64,
+        swarms: Vec<Swarm>,
+        awaiting: Vec<ServiceNode>,
+    },
+    /// The last `depth` blocks were replaced (by the blocks that follow)
+    Rollback { depth: u64 },
+    HardforkScheduled { height: u64, version: u8 },
+    SyncStarted { lag_blocks: u64, duration_ms: u64 },
+    PartitionStarted {
+        daemon: u16,
+        duration_ms: u64,
+        kind: PartitionKind,
+    },
+    /// A message accepted by the node on `port`
+    MessageSent {
+        pubkey: String,
+        data: String,
+        port: String,
+    },
+    /// All messages sent so far were checked
+    Check,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimelineEntry {
+    /// Time since genesis
+    pub time_ms: u64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// Writes every action out as JSON Lines as it happens,
+/// so that even an interrupted run can be replayed
+pub struct TimelineRecorder {
+    start: Instant,
+    file: Option<File>,
+}
+
+impl TimelineRecorder {
+    pub fn new(path: &Path) -> TimelineRecorder {
+        let file = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| File::create(path));
+
+        let file = match file {
+            Ok(file) => Some(file),
+            Err(e) => {
+                error!("could not create timeline {}: {}", path.display(), e);
+                None
+            }
+        };
+
+        TimelineRecorder {
+            start: Instant::now(),
+            file,
+        }
+    }
+
+    pub fn record(&mut self, action: Action) {
+        let entry = TimelineEntry {
+            time_ms: self.start.elapsed().as_millis() as u64,
+            action,
+        };
+
+        if let Some(file) = &mut self.file {
+            let line = serde_json::to_string(&entry).expect("could not construct json");
+            if let Err(e) = writeln!(file, "{}", line) {
+                error!("could not write to timeline: {}", e);
+            }
+        }
+    }
+}
+
+/// A recorded run, as loaded from `timeline.jsonl`
+pub struct Timeline {
+    pub seed: u64,
+    pub chain_params: ChainParams,
+    entries: Vec<TimelineEntry>,
+}
+
+impl Timeline {
+    pub fn load(path: &str) -> Result<Timeline, String> {
+        let file = File::open(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+
+        let mut entries = vec![];
+
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("could not read {}: {}", path, e))?;
+
+            // The last line may be cut short if the run was interrupted
+            match serde_json::from_str::<TimelineEntry>(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) if e.is_eof() => {
+                    warn!("{}:{}: skipping the rest of the timeline: {}", path, idx + 1, e);
+                    break;
+                }
+                Err(e) => return Err(format!("{}:{}: {}", path, idx + 1, e)),
+            }
+        }
+
+        match entries.first().map(|entry| &entry.action) {
+            Some(Action::Genesis {
+                seed,
+                hardforks,
+                sync_phases,
+                partitions,
+                daemons,
+            }) => Ok(Timeline {
+                seed: *seed,
+                chain_params: ChainParams {
+                    hardforks: hardforks.clone(),
+                    sync_phases: sync_phases.clone(),
+                    partitions: partitions.clone(),
+                    // Its outcome is part of the recorded blocks
+                    liveness: None,
+                    daemons: daemons.clone(),
+                },
+                entries,
+            }),
+            _ => Err(format!("{}: the timeline doesn't start with genesis", path)),
+        }
+    }
+}
+
+/// Re-execute `timeline` on a network freshly created with `Harness::reset`,
+/// keeping the original timing. Returns the result of the last check.
+pub fn replay(ctx: &Arc<Mutex<TestContext>>, timeline: &Timeline) -> Option<MessageReport> {
+    let start = Instant::now();
+    let mut report = None;
+
+    for entry in &timeline.entries[1..] {
+        let due = start + Duration::from_millis(entry.time_ms);
+        if let Some(wait) = due.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+
+        info!("replaying {:?}", entry.action);
+
+        if let Some(checked) = ctx.lock().unwrap().apply(&entry.action) {
+            report = Some(checked);
+        }
+    }
+
+    report
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+/// A `storage_server_ping` call, i.e. an uptime proof
+#[derive(Serialize, Debug, Clone)]
+pub struct Ping {
+    /// Storage server port, if reported
+    pub port: Option<String>,
+    /// Ed25519 (or legacy) pubkey, if reported
+    pub pubkey: Option<String>,
+    pub version: Option<String>,
+    /// The daemon that received the ping
+    pub lokid_port: u16,
+    /// Time since genesis
+    pub time_ms: u64,
+}
+
+impl Ping {
+    /// Storage servers have reported themselves differently over
+    /// time, so accept any of the known parameter names
+    pub fn from_params(params: &serde_json::Value, lokid_port: u16) -> Ping {
+        let port = ["https_port", "storage_port"]
+            .iter()
+            .find_map(|key| params.get(*key).and_then(|v| v.as_u64()))
+            .map(|port| port.to_string());
+
+        let pubkey = ["pubkey_ed25519", "ed25519_pubkey", "pubkey"]
+            .iter()
+            .find_map(|key| params.get(*key).and_then(|v| v.as_str()))
+            .map(|pk| pk.to_owned());
+
+        let version = match params.get("version").and_then(|v| v.as_array()) {
+            Some(parts) => Some(parts.iter().map(|v| v.to_string()).collect::<Vec<_>>()),
+            None => ["version_major", "version_minor", "version_patch"]
+                .iter()
+                .map(|key| params.get(*key).map(|v| v.to_string()))
+                .collect(),
+        };
+
+        Ping {
+            port,
+            pubkey,
+            version: version.map(|parts| parts.join(".")),
+            lokid_port,
+            time_ms: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NodePings {
+    pub count: u64,
+    pub last: Ping,
+}
+
+/// Pings received from each registered node, by port
+#[derive(Debug, Default)]
+pub struct PingLog {
+    nodes: BTreeMap<String, NodePings>,
+    /// Pings that couldn't be matched with a registered node
+    pub unknown: u64,
+}
+
+impl PingLog {
+    pub fn record(&mut self, port: &str, ping: Ping) {
+        match self.nodes.get_mut(port) {
+            Some(node) => {
+                node.count += 1;
+                node.last = ping;
+            }
+            None => {
+                self.nodes
+                    .insert(port.to_owned(), NodePings { count: 1, last: ping });
+            }
+        }
+    }
+
+    /// Time of the latest ping from the node on `port`
+    pub fn last_ms(&self, port: &str) -> Option<u64> {
+        self.nodes.get(port).map(|node| node.last.time_ms)
+    }
+
+    pub fn nodes(&self) -> &BTreeMap<String, NodePings> {
+        &self.nodes
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LivenessAction {
+    /// Recommissioned as soon as the node pings again
+    Decommission,
+    Deregister,
+}
+
+/// What happens to active nodes that haven't pinged for `window_ms`
+/// (counting from when they were first seen in a swarm)
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct LivenessPolicy {
+    pub window_ms: u64,
+    pub action: LivenessAction,
+    /// Credit of decommissioned nodes, see `NodeState::Decommissioned`
+    #[serde(default = "default_credit_blocks")]
+    pub credit_blocks: u64,
+}
+
+fn default_credit_blocks() -> u64 {
+    100
+}
+
+/// A node penalised by the liveness policy
+#[derive(Serialize, Debug, Clone)]
+pub struct LivenessFailure {
+    pub port: String,
+    /// Height of the current block when the policy kicked in
+    pub height: u64,
+    /// How long the node had been silent for
+    pub silent_ms: u64,
+    pub action: LivenessAction,
+}